@@ -0,0 +1,125 @@
+//! Rendering benchmarks. Each runs on the global thread pool and on a
+//! single-threaded pool, the sequential baseline the parallel passes are
+//! measured against. The full-render benchmark needs a font: point
+//! `SNAPSHOT_BENCH_FONT` at a TTF file, e.g. the bundled JetBrains Mono.
+
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion};
+use image::{ImageBuffer, Rgba, RgbaImage};
+use std::hint::black_box;
+use std::path::Path;
+
+use snapshot_generator::shadow::{apply_outer_shadow, composite_image_onto};
+use snapshot_generator::{load_font_from, render_with_font, Blending, Config, Input, Line, Span};
+
+const LINES: usize = 500;
+const SCALE: f32 = 3.0;
+
+/// Benchmark `routine` as `parallel` on the global pool and as `sequential`
+/// on one thread.
+fn bench_pools<O: Send>(
+    group: &mut BenchmarkGroup<WallTime>,
+    mut routine: impl FnMut() -> O + Send,
+) {
+    let sequential = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .expect("single-threaded pool should build");
+    group.bench_function("parallel", |b| b.iter(&mut routine));
+    group.bench_function("sequential", |b| {
+        b.iter(|| sequential.install(&mut routine))
+    });
+}
+
+fn card(width: u32, height: u32) -> RgbaImage {
+    ImageBuffer::from_pixel(width, height, Rgba([40, 44, 52, 255]))
+}
+
+fn sample_input() -> Input {
+    let lines = (0..LINES)
+        .map(|idx| Line {
+            text: format!("    let value_{idx} = compute(\"snapshot\", {idx}) * 42; // comment"),
+            spans: vec![
+                Span {
+                    start: 4,
+                    end: 7,
+                    fg: Some("#c678dd".to_string()),
+                    bg: None,
+                    bold: None,
+                    italic: None,
+                    underline: None,
+                    undercurl: None,
+                },
+                Span {
+                    start: 30,
+                    end: 40,
+                    fg: Some("#98c379".to_string()),
+                    bg: None,
+                    bold: None,
+                    italic: None,
+                    underline: None,
+                    undercurl: None,
+                },
+            ],
+        })
+        .collect();
+
+    Input {
+        protocol_version: None,
+        lines,
+        config: Config {
+            scale: SCALE,
+            line_numbers: true,
+            ..Config::default()
+        },
+        before: None,
+        source: None,
+    }
+}
+
+fn bench_shadow(c: &mut Criterion) {
+    let card = card(2400, 4200);
+    let mut group = c.benchmark_group("apply_outer_shadow 2400x4200");
+    bench_pools(&mut group, || {
+        apply_outer_shadow(
+            black_box(&card),
+            60.0,
+            0.5,
+            0,
+            24,
+            Rgba([255, 255, 255, 255]),
+            45,
+            Blending::Linear,
+        )
+    });
+    group.finish();
+}
+
+fn bench_composite(c: &mut Criterion) {
+    let overlay = card(2400, 4200);
+    let base = card(2600, 4400);
+    let mut group = c.benchmark_group("composite_image_onto 2400x4200");
+    bench_pools(&mut group, || {
+        let mut output = base.clone();
+        composite_image_onto(&mut output, black_box(&overlay), 100, 100, Blending::Linear);
+        output
+    });
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    let Ok(font_path) = std::env::var("SNAPSHOT_BENCH_FONT") else {
+        eprintln!("SNAPSHOT_BENCH_FONT is not set, skipping the full render benchmark");
+        return;
+    };
+    let font = load_font_from(Path::new(&font_path)).expect("benchmark font should load");
+    let input = sample_input();
+
+    let mut group = c.benchmark_group("render 500 lines at scale 3");
+    group.sample_size(10);
+    bench_pools(&mut group, || render_with_font(black_box(&input), &font));
+    group.finish();
+}
+
+criterion_group!(benches, bench_shadow, bench_composite, bench_render);
+criterion_main!(benches);
@@ -0,0 +1,489 @@
+//! Animations: typing, with frames that reveal the snippet character by
+//! character or line by line on a card measured for the finished code, or a
+//! transition from an earlier version (see [`crate::transition`]).
+
+use ab_glyph::{Font, FontVec, ScaleFont};
+use image::RgbaImage;
+
+use crate::glyphs::{text_advance, GlyphCache};
+use crate::input::{Animation, Reveal};
+use crate::render::{
+    apply_card_effects, backdrop, build_render_config, measure_layout, place_card, render_card,
+    PreparedLine, RenderConfig,
+};
+use crate::transition::Transition;
+use crate::{Input, Line};
+
+const DEFAULT_CHARS_PER_SECOND: f32 = 30.0;
+const DEFAULT_LINES_PER_SECOND: f32 = 4.0;
+/// Limits that keep the number of frames bounded. Options outside them are
+/// reported by validation and clamped here.
+pub const MAX_FPS: u32 = 100;
+pub const MIN_SPEED: f32 = 0.1;
+/// Longest `hold` and transition `duration`, in seconds.
+pub const MAX_SECONDS: f32 = 60.0;
+/// Time the cursor spends on, then off, while the finished snippet is held.
+const BLINK_MS: u32 = 500;
+
+/// How much of the snippet is visible: every line before `line` in full, and
+/// `line` up to byte `cut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Progress {
+    line: usize,
+    cut: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pose {
+    /// Typing with `progress` revealed.
+    Typed { progress: Progress, cursor: bool },
+    /// Transition `t` of the way (0 to 1) from `before` to `lines`.
+    Morph(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Step {
+    pose: Pose,
+    delay_ms: u32,
+}
+
+/// Progress after each revealed unit. Indentation appears at once when a line
+/// is started.
+fn units(lines: &[Line], reveal: Reveal) -> Vec<Progress> {
+    match reveal {
+        Reveal::Lines => lines
+            .iter()
+            .enumerate()
+            .map(|(line, text)| Progress {
+                line,
+                cut: text.text.len(),
+            })
+            .collect(),
+        Reveal::Chars => lines
+            .iter()
+            .enumerate()
+            .flat_map(|(line, text)| {
+                let text = text.text.as_str();
+                let indent = text.len() - text.trim_start().len();
+                let typed = text[indent..]
+                    .char_indices()
+                    .map(move |(idx, c)| indent + idx + c.len_utf8());
+                std::iter::once(indent)
+                    .chain(typed)
+                    .map(move |cut| Progress { line, cut })
+            })
+            .collect(),
+    }
+}
+
+fn push_step(steps: &mut Vec<Step>, pose: Pose, delay_ms: u32) {
+    match steps.last_mut() {
+        Some(last) if last.pose == pose => last.delay_ms += delay_ms,
+        _ => steps.push(Step { pose, delay_ms }),
+    }
+}
+
+fn typed(progress: Progress, cursor: bool) -> Pose {
+    Pose::Typed { progress, cursor }
+}
+
+/// Frame timing shared by both animations: the end of each frame in
+/// milliseconds, and how long the finished snippet is held.
+fn timing(animation: &Animation) -> (impl Fn(u32) -> u32, u32) {
+    let fps = animation.fps.clamp(1, MAX_FPS) as f32;
+    let frame_end = move |frame: u32| (frame as f32 * 1000.0 / fps).round() as u32;
+    let hold = animation.hold.clamp(0.0, MAX_SECONDS);
+    let hold_ms = ((hold * 1000.0).round() as u32).max(frame_end(1));
+    (frame_end, hold_ms)
+}
+
+/// Typing frames to render with their delays. Frames that would look the same
+/// are merged into one longer frame.
+fn schedule(lines: &[Line], animation: &Animation) -> Vec<Step> {
+    let fps = animation.fps.clamp(1, MAX_FPS) as f32;
+    let (frame_end, hold_ms) = timing(animation);
+
+    let units = units(lines, animation.reveal);
+    let Some(&finished) = units.last() else {
+        let empty = Progress { line: 0, cut: 0 };
+        return vec![Step {
+            pose: typed(empty, false),
+            delay_ms: hold_ms,
+        }];
+    };
+
+    let speed = animation
+        .speed
+        .filter(|speed| speed.is_finite() && *speed > 0.0)
+        .unwrap_or(match animation.reveal {
+            Reveal::Chars => DEFAULT_CHARS_PER_SECOND,
+            Reveal::Lines => DEFAULT_LINES_PER_SECOND,
+        })
+        .max(MIN_SPEED);
+
+    let mut steps = Vec::new();
+    for frame in 0.. {
+        let unit = (frame as f32 * speed / fps) as usize;
+        if unit + 1 >= units.len() {
+            break;
+        }
+        let delay = frame_end(frame + 1) - frame_end(frame);
+        push_step(&mut steps, typed(units[unit], animation.cursor), delay);
+    }
+
+    if !animation.cursor {
+        push_step(&mut steps, typed(finished, false), hold_ms);
+        return steps;
+    }
+    let mut held = 0;
+    while held < hold_ms {
+        let delay = BLINK_MS.min(hold_ms - held);
+        let visible = (held / BLINK_MS).is_multiple_of(2);
+        push_step(&mut steps, typed(finished, visible), delay);
+        held += delay;
+    }
+    steps
+}
+
+/// Transition frames: the old version held, `duration` seconds of motion,
+/// then the new version held.
+fn morph_schedule(animation: &Animation) -> Vec<Step> {
+    let (frame_end, hold_ms) = timing(animation);
+    let duration_ms = (animation.duration.clamp(0.0, MAX_SECONDS) * 1000.0).round() as u32;
+    let frames = (1..)
+        .find(|&frame| frame_end(frame) >= duration_ms)
+        .unwrap_or(1);
+
+    let mut steps = vec![Step {
+        pose: Pose::Morph(0.0),
+        delay_ms: hold_ms,
+    }];
+    for frame in 1..frames {
+        steps.push(Step {
+            pose: Pose::Morph(frame as f32 / frames as f32),
+            delay_ms: frame_end(frame + 1) - frame_end(frame),
+        });
+    }
+    steps.push(Step {
+        pose: Pose::Morph(1.0),
+        delay_ms: hold_ms,
+    });
+    steps
+}
+
+/// `prepared` cut off after byte `cut` of `text`.
+fn truncate<F, SF>(
+    prepared: &PreparedLine,
+    text: &str,
+    cut: usize,
+    scaled_font: &SF,
+) -> PreparedLine
+where
+    F: Font,
+    SF: ScaleFont<F>,
+{
+    let segments: Vec<_> = prepared
+        .segments
+        .iter()
+        .filter(|segment| segment.start < cut)
+        .map(|segment| {
+            let mut segment = segment.clone();
+            if segment.end > cut {
+                segment.end = cut;
+                segment.width = text_advance(&text[segment.start..cut], scaled_font);
+            }
+            segment
+        })
+        .collect();
+    let width = segments.iter().map(|segment| segment.width).sum();
+    PreparedLine { width, segments }
+}
+
+/// One encoded frame and how long it is shown.
+pub struct Frame {
+    pub image: RgbaImage,
+    pub delay_ms: u32,
+}
+
+/// Frames of the animation for an input, rendered on demand.
+pub struct Frames<'a> {
+    input: &'a Input,
+    font: &'a FontVec,
+    glyphs: &'a GlyphCache,
+    render: RenderConfig,
+    prepared_lines: Vec<PreparedLine>,
+    line_number_width: f32,
+    transition: Option<Transition<'a>>,
+    card_size: (u32, u32),
+    backdrop: RgbaImage,
+    margin: u32,
+    steps: Vec<Step>,
+}
+
+impl<'a> Frames<'a> {
+    /// Lay out `input` and plan its frames using `config.animation`, or the
+    /// default animation if it is unset. Inputs with `before` lines morph
+    /// from them; the card is sized to fit both versions.
+    pub fn new(input: &'a Input, font: &'a FontVec, glyphs: &'a GlyphCache) -> Self {
+        let config = &input.config;
+        let animation = config.animation.clone().unwrap_or_default();
+        let render = build_render_config(config);
+        let scaled_font = font.as_scaled(render.scale);
+        let (prepared_lines, line_number_width, mut width, mut height) =
+            measure_layout(&input.lines, config, &render, &scaled_font);
+
+        let mut transition = None;
+        if let Some(before) = &input.before {
+            let (before_prepared, before_line_numbers, before_width, before_height) =
+                measure_layout(before, config, &render, &scaled_font);
+            width = width.max(before_width);
+            height = height.max(before_height);
+            transition = Some(Transition::new(
+                before,
+                before_prepared,
+                before_line_numbers,
+                &input.lines,
+                prepared_lines.clone(),
+                line_number_width,
+                &render,
+                &scaled_font,
+            ));
+        }
+
+        let empty = render_card(
+            &[],
+            &[],
+            config,
+            &render,
+            font,
+            glyphs,
+            line_number_width,
+            width,
+            height,
+        );
+        let (backdrop, margin) =
+            backdrop(&apply_card_effects(empty, config, &render), config, &render);
+        let steps = match transition {
+            Some(_) => morph_schedule(&animation),
+            None => schedule(&input.lines, &animation),
+        };
+
+        Self {
+            input,
+            font,
+            glyphs,
+            render,
+            prepared_lines,
+            line_number_width,
+            transition,
+            card_size: (width, height),
+            backdrop,
+            margin,
+            steps,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Size of every frame.
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.backdrop.dimensions()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Frame> + '_ {
+        self.steps.iter().map(|step| Frame {
+            image: self.render_step(step),
+            delay_ms: step.delay_ms,
+        })
+    }
+
+    fn render_step(&self, step: &Step) -> RgbaImage {
+        let config = &self.input.config;
+        let card = match step.pose {
+            Pose::Typed { progress, cursor } => self.render_typed(progress, cursor),
+            Pose::Morph(t) => self.render_morph(t),
+        };
+
+        let card = apply_card_effects(card, config, &self.render);
+        place_card(
+            self.backdrop.clone(),
+            &card,
+            self.margin,
+            config,
+            &self.render,
+        )
+    }
+
+    fn render_morph(&self, t: f32) -> RgbaImage {
+        let config = &self.input.config;
+        let (width, height) = self.card_size;
+        let mut card = render_card(
+            &[],
+            &[],
+            config,
+            &self.render,
+            self.font,
+            self.glyphs,
+            self.line_number_width,
+            width,
+            height,
+        );
+        if let Some(transition) = &self.transition {
+            transition.draw(&mut card, t, config, &self.render, self.font, self.glyphs);
+        }
+        card
+    }
+
+    fn render_typed(&self, progress: Progress, cursor: bool) -> RgbaImage {
+        let config = &self.input.config;
+        let scaled_font = self.font.as_scaled(self.render.scale);
+        let Progress { line, cut } = progress;
+        let visible = (line + 1).min(self.input.lines.len());
+
+        let mut prepared = self.prepared_lines[..visible].to_vec();
+        if let Some(last) = prepared.get_mut(line) {
+            *last = truncate(last, &self.input.lines[line].text, cut, &scaled_font);
+        }
+
+        let (width, height) = self.card_size;
+        let mut card = render_card(
+            &self.input.lines[..visible],
+            &prepared,
+            config,
+            &self.render,
+            self.font,
+            self.glyphs,
+            self.line_number_width,
+            width,
+            height,
+        );
+        if cursor {
+            let x = self.render.scaled_padding as f32
+                + self.line_number_width
+                + prepared.get(line).map_or(0.0, |line| line.width);
+            let y =
+                self.render.scaled_padding as f32 + line as f32 * self.render.scaled_line_height;
+            draw_cursor(&mut card, &self.render, scaled_font.height(), x, y);
+        }
+        card
+    }
+}
+
+/// Solid bar cursor with its top-left corner at (`x`, `y`).
+fn draw_cursor(card: &mut RgbaImage, render: &RenderConfig, height: f32, x: f32, y: f32) {
+    let bar = (2.0 * render.render_scale).round().max(1.0) as u32;
+    let (left, top) = (x.round() as u32, y.round() as u32);
+    let right = (left + bar).min(card.width());
+    let bottom = (top + height.round() as u32).min(card.height());
+
+    for py in top..bottom {
+        for px in left..right {
+            card.put_pixel(px, py, render.default_fg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{morph_schedule, schedule, units, Pose, Progress};
+    use crate::input::{Animation, Reveal};
+    use crate::Line;
+
+    fn lines(texts: &[&str]) -> Vec<Line> {
+        texts
+            .iter()
+            .map(|text| Line {
+                text: text.to_string(),
+                spans: Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chars_skip_indentation_and_respect_utf8() {
+        let cuts: Vec<_> = units(&lines(&["  aé", ""]), Reveal::Chars)
+            .into_iter()
+            .map(|Progress { line, cut }| (line, cut))
+            .collect();
+        assert_eq!(cuts, vec![(0, 2), (0, 3), (0, 5), (1, 0)]);
+    }
+
+    #[test]
+    fn schedule_types_then_blinks_for_the_hold() {
+        let animation = Animation {
+            reveal: Reveal::Lines,
+            fps: 10,
+            speed: Some(5.0),
+            hold: 1.2,
+            cursor: true,
+            ..Animation::default()
+        };
+        let steps = schedule(&lines(&["a", "b", "c"]), &animation);
+
+        let (typing, cursor): (Vec<_>, Vec<_>) = steps
+            .iter()
+            .map(|step| match step.pose {
+                Pose::Typed { progress, cursor } => (progress.line, cursor),
+                Pose::Morph(_) => panic!("typing has no transition frames"),
+            })
+            .unzip();
+        assert_eq!(typing, vec![0, 1, 2, 2, 2]);
+        let delays: Vec<_> = steps.iter().map(|step| step.delay_ms).collect();
+        assert_eq!(delays, vec![200, 200, 500, 500, 200]);
+        assert_eq!(cursor, vec![true, true, true, false, true]);
+    }
+
+    #[test]
+    fn morph_holds_both_versions_around_the_transition() {
+        let animation = Animation {
+            fps: 10,
+            hold: 1.0,
+            duration: 0.4,
+            ..Animation::default()
+        };
+        let steps = morph_schedule(&animation);
+
+        let poses: Vec<_> = steps.iter().map(|step| step.pose).collect();
+        let morph = Pose::Morph;
+        assert_eq!(
+            poses,
+            vec![morph(0.0), morph(0.25), morph(0.5), morph(0.75), morph(1.0)]
+        );
+        let delays: Vec<_> = steps.iter().map(|step| step.delay_ms).collect();
+        assert_eq!(delays, vec![1000, 100, 100, 100, 1000]);
+    }
+
+    #[test]
+    fn empty_input_is_a_single_frame() {
+        let steps = schedule(&[], &Animation::default());
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].delay_ms, 2000);
+    }
+
+    #[test]
+    fn out_of_range_options_are_clamped() {
+        let animation = Animation {
+            fps: 1_000_000,
+            speed: Some(1e-9),
+            hold: 1e9,
+            duration: 1e9,
+            ..Animation::default()
+        };
+
+        let typing = schedule(&lines(&["ab"]), &animation);
+        assert_eq!(
+            typing.len(),
+            2 + 120,
+            "two characters, then 60 s of blinking"
+        );
+        let morph = morph_schedule(&animation);
+        assert_eq!(morph.len(), 60 * 100 + 1);
+        assert_eq!(morph[0].delay_ms, 60_000);
+    }
+}
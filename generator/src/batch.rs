@@ -0,0 +1,218 @@
+use ab_glyph::FontVec;
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+
+use crate::animation::Frames;
+use crate::encode::{encode_animation, encode_image, output_format};
+use crate::html::render_html;
+use crate::output::{encode_text, save_with, STDOUT_PATH};
+use crate::validate::parse_input;
+use crate::{render_with_cache, GlyphCache, Input, OutputFormat};
+
+/// Documents read from stdin: a single input, or a batch given as a JSON array
+/// or as several concatenated (e.g. newline-delimited) JSON objects.
+#[derive(Debug, PartialEq)]
+pub enum Documents {
+    Single(Value),
+    Batch(Vec<BatchItem>),
+}
+
+/// A batch item, or why its line of newline-delimited JSON could not be parsed.
+pub type BatchItem = Result<Value, String>;
+
+pub fn parse_documents(buffer: &str) -> Result<Documents> {
+    let mut values = match serde_json::Deserializer::from_str(buffer)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(values) => values,
+        Err(error) => {
+            return parse_lines(buffer)
+                .map(Documents::Batch)
+                .ok_or_else(|| anyhow!(error).context("Failed to parse JSON input"))
+        }
+    };
+
+    if values.len() > 1 {
+        return Ok(Documents::Batch(values.into_iter().map(Ok).collect()));
+    }
+
+    match values.pop() {
+        Some(Value::Array(items)) => Ok(Documents::Batch(items.into_iter().map(Ok).collect())),
+        Some(value) => Ok(Documents::Single(value)),
+        None => Err(anyhow!("No JSON input on stdin")),
+    }
+}
+
+/// Parse newline-delimited JSON one line at a time, so a malformed line fails
+/// only its own item. Returns `None` for a single line or when no line holds an
+/// object, as the input is then more likely one malformed document.
+fn parse_lines(buffer: &str) -> Option<Vec<BatchItem>> {
+    let items: Vec<_> = buffer
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str::<Value>(line)
+                .map_err(|error| format!("Failed to parse JSON on line {}: {error}", number + 1))
+        })
+        .collect();
+
+    let has_object = items
+        .iter()
+        .any(|item| matches!(item, Ok(Value::Object(_))));
+    (items.len() > 1 && has_object).then_some(items)
+}
+
+/// Outcome of one batch item, reported as a line of JSON.
+#[derive(Debug, Serialize)]
+pub struct BatchItemResult {
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Encoded output of items with `output_path = "-"`, as base64 or a data URI.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Where a batch item's output went.
+enum Saved {
+    Path(String),
+    Data(String),
+}
+
+/// Encode `input` as HTML, an animation or a still image, as its config asks.
+fn encode_item(
+    input: &Input,
+    format: OutputFormat,
+    font: &FontVec,
+    glyphs: &GlyphCache,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    if format == OutputFormat::Html {
+        writer
+            .write_all(render_html(input).as_bytes())
+            .context("Failed to write html")
+    } else if input.config.animation.is_some() {
+        encode_animation(&Frames::new(input, font, glyphs), format, input, writer)
+    } else {
+        let image = render_with_cache(input, font, glyphs);
+        encode_image(&image, format, input, writer)
+    }
+}
+
+fn render_item(raw: &Value, font: &FontVec, glyphs: &GlyphCache) -> Result<(Saved, Vec<String>)> {
+    let (input, issues) = parse_input(raw)?;
+    let output_path = input
+        .config
+        .output_path
+        .as_deref()
+        .ok_or_else(|| anyhow!("Batch items need an explicit config.output_path"))?;
+
+    let encode = |format, writer: &mut dyn Write| encode_item(&input, format, font, glyphs, writer);
+    let saved = if output_path == STDOUT_PATH {
+        // Results share stdout, so the data travels inside the item's JSON line.
+        let format = output_format(&input.config, None);
+        let mut bytes = Vec::new();
+        encode(format, &mut bytes)?;
+        Saved::Data(encode_text(&bytes, format, input.config.output_encoding))
+    } else {
+        Saved::Path(save_with(output_path, &input, encode)?)
+    };
+    Ok((saved, issues.iter().map(ToString::to_string).collect()))
+}
+
+/// Render every item in parallel, sharing one font and glyph cache. A failing
+/// item does not stop the rest; results keep the input order. Clipboard copies
+/// are skipped.
+pub fn render_batch(items: &[BatchItem], font: &FontVec) -> Vec<BatchItemResult> {
+    let glyphs = GlyphCache::new();
+    items
+        .par_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            match item
+                .as_ref()
+                .map_err(|error| anyhow!("{error}"))
+                .and_then(|raw| render_item(raw, font, &glyphs))
+            {
+                Ok((saved, warnings)) => {
+                    let (path, data) = match saved {
+                        Saved::Path(path) => (Some(path), None),
+                        Saved::Data(data) => (None, Some(data)),
+                    };
+                    BatchItemResult {
+                        index,
+                        path,
+                        data,
+                        error: None,
+                        warnings,
+                    }
+                }
+                Err(error) => BatchItemResult {
+                    index,
+                    path: None,
+                    data: None,
+                    error: Some(format!("{error:#}")),
+                    warnings: Vec::new(),
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_documents, Documents};
+    use serde_json::json;
+
+    #[test]
+    fn single_object_is_not_a_batch() {
+        let documents = parse_documents(r#"{"lines": [], "config": {}}"#).unwrap();
+
+        assert_eq!(
+            documents,
+            Documents::Single(json!({ "lines": [], "config": {} }))
+        );
+    }
+
+    #[test]
+    fn arrays_and_ndjson_are_batches() {
+        let array = parse_documents(r#"[{"a": 1}, {"a": 2}]"#).unwrap();
+        let ndjson = parse_documents("{\"a\": 1}\n{\"a\": 2}\n").unwrap();
+
+        let expected = Documents::Batch(vec![Ok(json!({ "a": 1 })), Ok(json!({ "a": 2 }))]);
+        assert_eq!(array, expected);
+        assert_eq!(ndjson, expected);
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        assert!(parse_documents("  \n").is_err());
+        assert!(parse_documents("{\"a\": 1} nope").is_err());
+        assert!(parse_documents("{\n  \"lines\": [,\n}\n").is_err());
+    }
+
+    #[test]
+    fn malformed_ndjson_lines_fail_alone() {
+        let Documents::Batch(items) =
+            parse_documents("{\"a\": 1}\n{\"a\": \n\n{\"a\": 3}\n").unwrap()
+        else {
+            panic!("expected a batch");
+        };
+
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0], Ok(json!({ "a": 1 })));
+        assert!(items[1]
+            .as_ref()
+            .is_err_and(|error| error.starts_with("Failed to parse JSON on line 2:")));
+        assert_eq!(items[2], Ok(json!({ "a": 3 })));
+    }
+}
@@ -0,0 +1,136 @@
+//! Pixel blending in either sRGB or linear-light space.
+
+use image::Rgba;
+use std::sync::LazyLock;
+
+use crate::input::Blending;
+
+/// Resolution of the linear-to-sRGB lookup table.
+const LINEAR_STEPS: usize = 4096;
+
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    std::array::from_fn(|value| {
+        let c = value as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+});
+
+static LINEAR_TO_SRGB: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    (0..LINEAR_STEPS)
+        .map(|step| {
+            let l = step as f32 / (LINEAR_STEPS - 1) as f32;
+            let c = if l <= 0.003_130_8 {
+                l * 12.92
+            } else {
+                1.055 * l.powf(1.0 / 2.4) - 0.055
+            };
+            (c * 255.0).round().clamp(0.0, 255.0) as u8
+        })
+        .collect()
+});
+
+pub fn srgb_to_linear(value: u8) -> f32 {
+    SRGB_TO_LINEAR[value as usize]
+}
+
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let step = (value.clamp(0.0, 1.0) * (LINEAR_STEPS - 1) as f32).round();
+    LINEAR_TO_SRGB[step as usize]
+}
+
+/// Cover `dst` with `color` at the given coverage, as antialiased text does.
+pub fn mix(dst: Rgba<u8>, color: Rgba<u8>, coverage: f32, blending: Blending) -> Rgba<u8> {
+    let lerp = |a: f32, b: f32| a * (1.0 - coverage) + b * coverage;
+
+    match blending {
+        Blending::Srgb => Rgba(std::array::from_fn(|channel| {
+            lerp(dst[channel] as f32, color[channel] as f32).clamp(0.0, 255.0) as u8
+        })),
+        Blending::Linear => {
+            let channel =
+                |c: usize| linear_to_srgb(lerp(srgb_to_linear(dst[c]), srgb_to_linear(color[c])));
+            let alpha = lerp(dst[3] as f32, color[3] as f32)
+                .round()
+                .clamp(0.0, 255.0);
+            Rgba([channel(0), channel(1), channel(2), alpha as u8])
+        }
+    }
+}
+
+/// Porter-Duff "source over" with straight (non-premultiplied) alpha.
+pub fn source_over(src: Rgba<u8>, dst: Rgba<u8>, blending: Blending) -> Rgba<u8> {
+    let sa = src[3] as f32 / 255.0;
+    if sa <= 0.0 {
+        return dst;
+    }
+
+    let da = dst[3] as f32 / 255.0;
+    let out_a = sa + da * (1.0 - sa);
+    if out_a <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    let over = |s: f32, d: f32| (s * sa + d * da * (1.0 - sa)) / out_a;
+    match blending {
+        Blending::Srgb => {
+            let channel = |c: usize| over(src[c] as f32, dst[c] as f32) as u8;
+            Rgba([channel(0), channel(1), channel(2), (out_a * 255.0) as u8])
+        }
+        Blending::Linear => {
+            let channel =
+                |c: usize| linear_to_srgb(over(srgb_to_linear(src[c]), srgb_to_linear(dst[c])));
+            let alpha = (out_a * 255.0).round() as u8;
+            Rgba([channel(0), channel(1), channel(2), alpha])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{linear_to_srgb, mix, source_over, srgb_to_linear};
+    use crate::input::Blending;
+    use image::Rgba;
+
+    #[test]
+    fn srgb_round_trips_through_linear() {
+        for value in 0..=255u8 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn source_over_matches_expected_alpha_blend() {
+        let src = Rgba([200, 100, 50, 128]);
+        let dst = Rgba([10, 20, 30, 255]);
+        let out = source_over(src, dst, Blending::Srgb);
+
+        assert_eq!(out[3], 255);
+        assert!(out[0] > dst[0]);
+        assert!(out[1] > dst[1]);
+    }
+
+    #[test]
+    fn linear_blending_is_brighter_at_half_coverage() {
+        let black = Rgba([0, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+
+        assert_eq!(
+            mix(black, white, 0.5, Blending::Srgb),
+            Rgba([127, 127, 127, 255])
+        );
+        assert_eq!(
+            mix(black, white, 0.5, Blending::Linear),
+            Rgba([188, 188, 188, 255])
+        );
+
+        let shadow = Rgba([0, 0, 0, 128]);
+        assert!(
+            source_over(shadow, white, Blending::Linear)[0]
+                > source_over(shadow, white, Blending::Srgb)[0]
+        );
+    }
+}
@@ -0,0 +1,396 @@
+//! Clipboard backends. The system clipboard is used directly; where it fails,
+//! as on some Wayland compositors or over SSH, the `wl-copy`, `xclip` and
+//! `xsel` commands and finally an OSC 52 terminal sequence are tried.
+
+use anyhow::{anyhow, Context, Result};
+use image::RgbaImage;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::encode::fast_png;
+use crate::html::{plain_text, render_html};
+use crate::input::ClipboardBackend;
+use crate::utils::base64;
+use crate::Input;
+
+/// Backends tried in order by `clipboard_backend = "auto"`.
+const AUTO_BACKENDS: [ClipboardBackend; 5] = [
+    ClipboardBackend::System,
+    ClipboardBackend::WlCopy,
+    ClipboardBackend::Xclip,
+    ClipboardBackend::Xsel,
+    ClipboardBackend::Osc52,
+];
+
+/// Representations of the snapshot placed on the clipboard together, so the
+/// application pasting it can pick the one it handles best.
+struct Flavors<'a> {
+    image: Option<&'a RgbaImage>,
+    html: &'a str,
+    text: &'a str,
+}
+
+/// A copy that succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Copied {
+    /// Backend that took the copy, the first that worked for `auto`.
+    pub backend: ClipboardBackend,
+    /// Whether the snapshot itself, the image or the HTML, was placed rather
+    /// than only its plain text.
+    pub snapshot_copied: bool,
+}
+
+impl Copied {
+    /// Warning for `save = false` when only the plain text was copied.
+    pub fn text_only_warning(&self) -> Option<String> {
+        (!self.snapshot_copied).then(|| {
+            format!(
+                "{} only copied the plain text, saving the snapshot instead",
+                backend_name(self.backend)
+            )
+        })
+    }
+}
+
+fn backend_name(backend: ClipboardBackend) -> &'static str {
+    match backend {
+        ClipboardBackend::Auto => "auto",
+        ClipboardBackend::System => "system",
+        ClipboardBackend::WlCopy => "wl-copy",
+        ClipboardBackend::Xclip => "xclip",
+        ClipboardBackend::Xsel => "xsel",
+        ClipboardBackend::Osc52 => "osc52",
+    }
+}
+
+/// arboard sets one format per call and replaces the clipboard each time, so
+/// it holds either the image or the HTML with its plain text.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn copy_with_system_clipboard(flavors: &Flavors) -> Result<()> {
+    let mut clipboard = arboard::Clipboard::new().context("Failed to access clipboard")?;
+    match flavors.image {
+        Some(image) => clipboard.set_image(arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: image.as_raw().into(),
+        }),
+        None => clipboard.set_html(flavors.html, Some(flavors.text)),
+    }
+    .map_err(|error| anyhow!("{error}"))
+}
+
+/// Write every flavor to the general pasteboard as one item.
+#[cfg(target_os = "macos")]
+fn copy_with_system_clipboard(flavors: &Flavors) -> Result<()> {
+    use objc2::rc::Retained;
+    use objc2::{msg_send, ClassType};
+    use objc2_app_kit::{
+        NSPasteboard, NSPasteboardTypeHTML, NSPasteboardTypePNG, NSPasteboardTypeString,
+    };
+    use objc2_foundation::{NSData, NSString};
+
+    let png = flavors.image.map(fast_png).transpose()?;
+    // `generalPasteboard` returns nil instead of failing when run as a daemon.
+    let pasteboard: Option<Retained<NSPasteboard>> =
+        unsafe { msg_send![NSPasteboard::class(), generalPasteboard] };
+    let pasteboard = pasteboard.ok_or_else(|| anyhow!("no pasteboard"))?;
+    // Without a charset, pasting applications may read the HTML as Latin 1.
+    let html = format!(
+        "<html><head><meta http-equiv=\"content-type\" content=\"text/html; charset=utf-8\">\
+         </head><body>{}</body></html>",
+        flavors.html
+    );
+
+    pasteboard.clearContents();
+    let mut written = true;
+    if let Some(png) = &png {
+        written &= pasteboard.setData_forType(Some(&NSData::with_bytes(png)), unsafe {
+            NSPasteboardTypePNG
+        });
+    }
+    written &=
+        pasteboard.setString_forType(&NSString::from_str(&html), unsafe { NSPasteboardTypeHTML });
+    written &= pasteboard.setString_forType(&NSString::from_str(flavors.text), unsafe {
+        NSPasteboardTypeString
+    });
+    if written {
+        Ok(())
+    } else {
+        Err(anyhow!("the pasteboard refused the data"))
+    }
+}
+
+/// `image` as a bottom-up 32-bit `CF_DIBV5` bitmap with straight alpha, from
+/// which Windows also synthesizes `CF_DIB` and `CF_BITMAP`.
+#[cfg(any(windows, test))]
+fn dibv5(image: &RgbaImage) -> Vec<u8> {
+    const HEADER_LEN: u32 = 124;
+    const BI_BITFIELDS: u32 = 3;
+    const LCS_SRGB: u32 = 0x7352_4742;
+    const LCS_GM_IMAGES: u32 = 4;
+
+    let (width, height) = image.dimensions();
+    let image_len = width * height * 4;
+    let mut dib = Vec::with_capacity((HEADER_LEN + image_len) as usize);
+    for field in [HEADER_LEN, width, height] {
+        dib.extend_from_slice(&field.to_le_bytes());
+    }
+    dib.extend_from_slice(&1u16.to_le_bytes()); // planes
+    dib.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+    for field in [BI_BITFIELDS, image_len, 0, 0, 0, 0] {
+        dib.extend_from_slice(&field.to_le_bytes());
+    }
+    // Red, green, blue and alpha masks, then the color space.
+    for field in [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000, LCS_SRGB] {
+        dib.extend_from_slice(&field.to_le_bytes());
+    }
+    // Endpoints and gamma, unused for sRGB.
+    dib.resize(dib.len() + 48, 0);
+    for field in [LCS_GM_IMAGES, 0, 0, 0] {
+        dib.extend_from_slice(&field.to_le_bytes());
+    }
+
+    for row in image.rows().rev() {
+        for pixel in row {
+            dib.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+    }
+    dib
+}
+
+/// Write every flavor while holding the clipboard open: the image as PNG and as
+/// a bitmap with alpha for older applications, then the HTML and the text.
+#[cfg(windows)]
+fn copy_with_system_clipboard(flavors: &Flavors) -> Result<()> {
+    use clipboard_win::formats::CF_DIBV5;
+    use clipboard_win::options::NoClear;
+    use clipboard_win::{raw, register_format, Clipboard, ErrorCode};
+
+    let win_error = |error: ErrorCode| anyhow!("{error}");
+    let registered = |name: &str| {
+        register_format(name)
+            .map(|format| format.get())
+            .ok_or_else(|| anyhow!("could not register the {name} format"))
+    };
+
+    let _open = Clipboard::new_attempts(10).map_err(win_error)?;
+    raw::empty().map_err(win_error)?;
+    if let Some(image) = flavors.image {
+        raw::set_without_clear(registered("PNG")?, &fast_png(image)?).map_err(win_error)?;
+        raw::set_without_clear(CF_DIBV5, &dibv5(image)).map_err(win_error)?;
+    }
+    raw::set_html(registered("HTML Format")?, flavors.html).map_err(win_error)?;
+    raw::set_string_with(flavors.text, NoClear).map_err(win_error)
+}
+
+/// Run `program` with `data` on stdin. Its output is discarded because these
+/// tools fork a child that keeps serving the selection after the copy.
+fn pipe_to_command(program: &str, args: &[&str], data: &[u8]) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => anyhow!("not installed"),
+            _ => anyhow!(error),
+        })?;
+    let written = child.stdin.take().expect("stdin is piped").write_all(data);
+    let status = child.wait()?;
+    written?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{status}"))
+    }
+}
+
+/// The richest flavor, for commands that copy a single MIME type.
+fn single_flavor(flavors: &Flavors) -> Result<(&'static str, Vec<u8>)> {
+    match flavors.image {
+        Some(image) => Ok(("image/png", fast_png(image)?)),
+        None => Ok(("text/html", flavors.html.as_bytes().to_vec())),
+    }
+}
+
+/// Copy with a command that takes a MIME type: `wl-copy` or `xclip`.
+fn copy_with_typed_command(program: &str, args: &[&str], flavors: &Flavors) -> Result<()> {
+    let (mime_type, data) = single_flavor(flavors)?;
+    let args: Vec<_> = args
+        .iter()
+        .map(|&arg| if arg == "{type}" { mime_type } else { arg })
+        .collect();
+    pipe_to_command(program, &args, &data)
+}
+
+/// OSC 52 sequence that sets the clipboard to `text`, wrapped in tmux's
+/// passthrough sequence when running inside tmux.
+fn osc52_sequence(text: &str, tmux: bool) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    if tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+/// Ask the terminal to copy `text`. The terminal does not answer, so this
+/// succeeds whenever there is a terminal to write to.
+fn copy_with_osc52(text: &str) -> Result<()> {
+    let mut tty = OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .context("no terminal")?;
+    let sequence = osc52_sequence(text, std::env::var_os("TMUX").is_some());
+    tty.write_all(sequence.as_bytes())
+        .and_then(|()| tty.flush())
+        .context("failed to write to the terminal")
+}
+
+/// Copy with `backend`, returning whether the snapshot itself was placed.
+/// `xsel` and OSC 52 only carry text, so they copy the plain source.
+fn copy_with(flavors: &Flavors, backend: ClipboardBackend) -> Result<bool> {
+    match backend {
+        ClipboardBackend::Auto => unreachable!("auto is resolved by copy"),
+        ClipboardBackend::System => copy_with_system_clipboard(flavors).map(|()| true),
+        ClipboardBackend::WlCopy => {
+            copy_with_typed_command("wl-copy", &["--type", "{type}"], flavors).map(|()| true)
+        }
+        ClipboardBackend::Xclip => copy_with_typed_command(
+            "xclip",
+            &["-selection", "clipboard", "-t", "{type}", "-i"],
+            flavors,
+        )
+        .map(|()| true),
+        ClipboardBackend::Xsel => {
+            pipe_to_command("xsel", &["--clipboard", "--input"], flavors.text.as_bytes())
+                .map(|()| false)
+        }
+        ClipboardBackend::Osc52 => copy_with_osc52(flavors.text).map(|()| false),
+    }
+}
+
+/// Copy with `backend`, or with the first auto backend that works. The error
+/// lists why each backend that was tried failed.
+fn copy(flavors: &Flavors, backend: ClipboardBackend) -> Result<Copied> {
+    if backend != ClipboardBackend::Auto {
+        return copy_with(flavors, backend)
+            .map(|snapshot_copied| Copied {
+                backend,
+                snapshot_copied,
+            })
+            .map_err(|error| {
+                anyhow!(
+                    "Failed to copy to clipboard with {}: {error:#}",
+                    backend_name(backend)
+                )
+            });
+    }
+
+    let mut failures = Vec::new();
+    for backend in AUTO_BACKENDS {
+        match copy_with(flavors, backend) {
+            Ok(snapshot_copied) => {
+                return Ok(Copied {
+                    backend,
+                    snapshot_copied,
+                })
+            }
+            Err(error) => failures.push(format!("{} ({error:#})", backend_name(backend))),
+        }
+    }
+    Err(anyhow!(
+        "Failed to copy to clipboard, tried {}",
+        failures.join(", ")
+    ))
+}
+
+/// Place `image` on the clipboard together with an HTML rendering and the
+/// plain text of `input`, as far as the backend can hold them.
+pub fn copy_to_clipboard(image: &RgbaImage, input: &Input) -> Result<Copied> {
+    let flavors = Flavors {
+        image: Some(image),
+        html: &render_html(input),
+        text: &plain_text(input),
+    };
+    copy(&flavors, input.config.clipboard_backend)
+}
+
+/// Place `html` on the clipboard as `text/html`, with the plain text of
+/// `input` for applications and backends that only handle text.
+pub fn copy_html_to_clipboard(html: &str, input: &Input) -> Result<Copied> {
+    let flavors = Flavors {
+        image: None,
+        html,
+        text: &plain_text(input),
+    };
+    copy(&flavors, input.config.clipboard_backend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dibv5, osc52_sequence, single_flavor, Copied, Flavors};
+    use crate::input::ClipboardBackend;
+    use image::RgbaImage;
+
+    #[test]
+    fn osc52_is_wrapped_for_tmux() {
+        assert_eq!(osc52_sequence("hi", false), "\x1b]52;c;aGk=\x07");
+        assert_eq!(
+            osc52_sequence("hi", true),
+            "\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\"
+        );
+    }
+
+    #[test]
+    fn dibv5_keeps_alpha_bottom_up() {
+        let image = RgbaImage::from_fn(2, 2, |x, y| image::Rgba([x as u8, y as u8, 9, 128]));
+        let dib = dibv5(&image);
+        let field = |offset: usize| u32::from_le_bytes(dib[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(dib.len(), 124 + 2 * 2 * 4);
+        assert_eq!((field(0), field(4), field(8)), (124, 2, 2));
+        assert_eq!(field(52), 0xff00_0000, "alpha mask");
+        // The first stored row is the bottom one, as BGRA.
+        assert_eq!(&dib[124..132], &[9, 1, 0, 128, 9, 1, 1, 128]);
+    }
+
+    #[test]
+    fn text_only_copies_are_reported() {
+        let copied = |backend, snapshot_copied| Copied {
+            backend,
+            snapshot_copied,
+        };
+
+        assert_eq!(
+            copied(ClipboardBackend::WlCopy, true).text_only_warning(),
+            None
+        );
+        assert_eq!(
+            copied(ClipboardBackend::Osc52, false).text_only_warning(),
+            Some("osc52 only copied the plain text, saving the snapshot instead".to_string())
+        );
+    }
+
+    #[test]
+    fn single_type_commands_prefer_the_image() {
+        let image = RgbaImage::new(1, 1);
+        let mut flavors = Flavors {
+            image: Some(&image),
+            html: "<pre>hi</pre>",
+            text: "hi",
+        };
+        let (mime_type, data) = single_flavor(&flavors).unwrap();
+        assert_eq!(mime_type, "image/png");
+        assert!(data.starts_with(b"\x89PNG"));
+
+        flavors.image = None;
+        let (mime_type, data) = single_flavor(&flavors).unwrap();
+        assert_eq!(
+            (mime_type, data.as_slice()),
+            ("text/html", &b"<pre>hi</pre>"[..])
+        );
+    }
+}
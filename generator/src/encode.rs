@@ -0,0 +1,442 @@
+//! Encoders for the supported output formats.
+
+use anyhow::{anyhow, Context, Result};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::jpeg::{JpegEncoder, PixelDensity, PixelDensityUnit};
+#[cfg(feature = "native")]
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{Delay, DynamicImage, ExtendedColorType, Frame, ImageEncoder, Rgba, RgbaImage};
+use std::io::Write;
+use std::path::Path;
+
+use crate::animation::Frames;
+use crate::blend::source_over;
+use crate::html::render_html;
+use crate::input::{Blending, OutputFormat};
+use crate::metadata::{dpi, exif_resolution};
+use crate::png_writer::{write_apng, write_png};
+use crate::utils::hex_to_rgba;
+use crate::{Config, Input};
+
+impl OutputFormat {
+    /// Format implied by the extension of `path`, if it is a known one.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "webp" => Some(Self::Webp),
+            "avif" => Some(Self::Avif),
+            "gif" => Some(Self::Gif),
+            "html" | "htm" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+            Self::Gif => "gif",
+            Self::Html => "html",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+            Self::Gif => "image/gif",
+            Self::Html => "text/html",
+        }
+    }
+}
+
+/// The configured format, else the one implied by `output_path`, else PNG.
+pub fn output_format(config: &Config, output_path: Option<&str>) -> OutputFormat {
+    config
+        .format
+        .or_else(|| output_path.and_then(OutputFormat::from_path))
+        .unwrap_or(OutputFormat::Png)
+}
+
+/// Composite `image` onto an opaque version of `background`.
+pub(crate) fn flatten(image: &RgbaImage, background: Rgba<u8>, blending: Blending) -> RgbaImage {
+    let background = Rgba([background[0], background[1], background[2], 255]);
+    let mut flattened = image.clone();
+    flattened
+        .pixels_mut()
+        .for_each(|pixel| *pixel = source_over(*pixel, background, blending));
+    flattened
+}
+
+/// Quickly compressed PNG without metadata, for previews and the clipboard.
+#[cfg(feature = "native")]
+pub(crate) fn fast_png(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    PngEncoder::new_with_quality(&mut bytes, CompressionType::Fast, FilterType::Adaptive)
+        .write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ExtendedColorType::Rgba8,
+        )
+        .context("Failed to encode PNG")?;
+    Ok(bytes)
+}
+
+/// NeuQuant sampling factor for GIF palettes, trading a little color accuracy
+/// for much faster encoding of large frames.
+const GIF_SPEED: i32 = 10;
+
+/// GIF only has binary transparency, so flatten onto an opaque outer
+/// background first.
+fn gif_frame(image: &RgbaImage, config: &Config) -> RgbaImage {
+    let background = hex_to_rgba(&config.outer_background);
+    if background[3] == 255 {
+        flatten(image, background, config.blending)
+    } else {
+        image.clone()
+    }
+}
+
+#[cfg(feature = "webp-lossy")]
+fn write_lossy_webp(
+    image: &RgbaImage,
+    quality: u8,
+    exif: &[u8],
+    mut writer: impl Write,
+) -> Result<()> {
+    let (width, height) = image.dimensions();
+    let encoded = webp::Encoder::from_rgba(image.as_raw(), width, height).encode(quality as f32);
+    let encoded = crate::metadata::add_webp_exif(&encoded, width, height, exif)?;
+    writer.write_all(&encoded).context("Failed to encode webp")
+}
+
+#[cfg(not(feature = "webp-lossy"))]
+fn write_lossy_webp(_: &RgbaImage, _: u8, _: &[u8], _: impl Write) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "Lossy WebP needs a generator built with the `webp-lossy` feature, \
+         set webp_lossless = true or use another format"
+    ))
+}
+
+/// libwebp animation encoder, deleted on drop.
+#[cfg(feature = "webp-lossy")]
+struct WebpAnimEncoder(*mut libwebp_sys::WebPAnimEncoder);
+
+#[cfg(feature = "webp-lossy")]
+impl WebpAnimEncoder {
+    fn new(width: u32, height: u32) -> Result<Self> {
+        use libwebp_sys::*;
+
+        let mut options = std::mem::MaybeUninit::uninit();
+        // SAFETY: `options` is initialized by libwebp before it is read.
+        let encoder = unsafe {
+            if WebPAnimEncoderOptionsInitInternal(options.as_mut_ptr(), WEBP_MUX_ABI_VERSION as _)
+                == 0
+            {
+                return Err(anyhow!("Failed to configure webp encoder"));
+            }
+            let mut options = options.assume_init();
+            options.anim_params.bgcolor = 0;
+            WebPAnimEncoderNewInternal(
+                width as i32,
+                height as i32,
+                &options,
+                WEBP_MUX_ABI_VERSION as _,
+            )
+        };
+        if encoder.is_null() {
+            return Err(anyhow!("Failed to configure webp encoder"));
+        }
+        Ok(Self(encoder))
+    }
+
+    fn error(&self) -> anyhow::Error {
+        // SAFETY: libwebp returns a NUL-terminated string owned by the encoder.
+        let message =
+            unsafe { std::ffi::CStr::from_ptr(libwebp_sys::WebPAnimEncoderGetError(self.0)) };
+        anyhow!("Failed to encode webp: {}", message.to_string_lossy())
+    }
+
+    fn add(
+        &mut self,
+        image: &RgbaImage,
+        timestamp: i32,
+        config: &libwebp_sys::WebPConfig,
+    ) -> Result<()> {
+        use libwebp_sys::*;
+
+        let (width, height) = image.dimensions();
+        let mut picture =
+            WebPPicture::new().map_err(|_| anyhow!("Failed to configure webp encoder"))?;
+        picture.use_argb = 1;
+        picture.width = width as i32;
+        picture.height = height as i32;
+        // SAFETY: the pixel buffer holds `height` rows of `width * 4` bytes and
+        // libwebp copies it into `picture`, which is freed before returning.
+        let added = unsafe {
+            let added =
+                WebPPictureImportRGBA(&mut picture, image.as_raw().as_ptr(), width as i32 * 4) != 0
+                    && WebPAnimEncoderAdd(self.0, &mut picture, timestamp, config) != 0;
+            WebPPictureFree(&mut picture);
+            added
+        };
+        if added {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Close the animation at `end_ms`, which sets the last frame's duration.
+    fn finish(self, end_ms: i32) -> Result<Vec<u8>> {
+        use libwebp_sys::*;
+
+        let mut data = WebPData::default();
+        // SAFETY: a null frame marks the end of the input, and `data` is freed
+        // once its bytes are copied.
+        unsafe {
+            if WebPAnimEncoderAdd(self.0, std::ptr::null_mut(), end_ms, std::ptr::null()) == 0
+                || WebPAnimEncoderAssemble(self.0, &mut data) == 0
+            {
+                return Err(self.error());
+            }
+            let encoded = std::slice::from_raw_parts(data.bytes, data.size).to_vec();
+            WebPDataClear(&mut data);
+            Ok(encoded)
+        }
+    }
+}
+
+#[cfg(feature = "webp-lossy")]
+impl Drop for WebpAnimEncoder {
+    fn drop(&mut self) {
+        // SAFETY: the encoder was created by `WebpAnimEncoder::new`.
+        unsafe { libwebp_sys::WebPAnimEncoderDelete(self.0) }
+    }
+}
+
+/// Animated WebP that loops forever. Frames are handed to libwebp as they are
+/// rendered, so only the encoder's own state is kept in memory.
+#[cfg(feature = "webp-lossy")]
+fn write_animated_webp(
+    frames: impl IntoIterator<Item = crate::animation::Frame>,
+    (width, height): (u32, u32),
+    config: &Config,
+    mut writer: impl Write,
+) -> Result<()> {
+    let mut webp_config =
+        libwebp_sys::WebPConfig::new().map_err(|_| anyhow!("Failed to configure webp encoder"))?;
+    webp_config.lossless = config.webp_lossless as i32;
+    webp_config.quality = config.quality.clamp(1, 100) as f32;
+
+    let mut encoder = WebpAnimEncoder::new(width, height)?;
+    let mut timestamp = 0i32;
+    for frame in frames {
+        encoder.add(&frame.image, timestamp, &webp_config)?;
+        timestamp = timestamp.saturating_add(frame.delay_ms as i32);
+    }
+    let encoded = encoder.finish(timestamp)?;
+
+    let exif = exif_resolution(dpi(config));
+    let encoded = crate::metadata::add_webp_exif(&encoded, width, height, &exif)?;
+    writer.write_all(&encoded).context("Failed to encode webp")
+}
+
+#[cfg(not(feature = "webp-lossy"))]
+fn write_animated_webp(
+    _: impl IntoIterator<Item = crate::animation::Frame>,
+    _: (u32, u32),
+    _: &Config,
+    _: impl Write,
+) -> Result<()> {
+    Err(anyhow!(
+        "Animated WebP needs a generator built with the `webp-lossy` feature, \
+         save the animation as png or gif instead"
+    ))
+}
+
+/// Encode `image` as `format` using the quality settings from `input.config`,
+/// recording its DPI in the format's metadata (AVIF and WebP store it as EXIF).
+/// HTML is generated from `input` and ignores the image.
+pub fn encode_image(
+    image: &RgbaImage,
+    format: OutputFormat,
+    input: &Input,
+    mut writer: impl Write,
+) -> Result<()> {
+    let config = &input.config;
+    let (width, height) = image.dimensions();
+    let quality = config.quality.clamp(1, 100);
+    let dpi = dpi(config);
+
+    match format {
+        OutputFormat::Png => return write_png(image, input, writer),
+        OutputFormat::Jpeg => {
+            let background = hex_to_rgba(&config.outer_background);
+            let rgb = DynamicImage::from(flatten(image, background, config.blending)).into_rgb8();
+            let density = dpi.round().clamp(1.0, u16::MAX as f32) as u16;
+            let mut encoder = JpegEncoder::new_with_quality(writer, quality);
+            encoder.set_pixel_density(PixelDensity {
+                density: (density, density),
+                unit: PixelDensityUnit::Inches,
+            });
+            encoder.write_image(rgb.as_raw(), width, height, ExtendedColorType::Rgb8)
+        }
+        OutputFormat::Webp if !config.webp_lossless => {
+            return write_lossy_webp(image, quality, &exif_resolution(dpi), writer)
+        }
+        OutputFormat::Webp => {
+            let mut encoder = WebPEncoder::new_lossless(writer);
+            encoder
+                .set_exif_metadata(exif_resolution(dpi))
+                .context("Failed to encode webp metadata")?;
+            encoder.write_image(image.as_raw(), width, height, ExtendedColorType::Rgba8)
+        }
+        OutputFormat::Avif => {
+            let mut encoder = AvifEncoder::new_with_speed_quality(
+                writer,
+                config.avif_speed.clamp(1, 10),
+                quality,
+            );
+            encoder
+                .set_exif_metadata(exif_resolution(dpi))
+                .context("Failed to encode avif metadata")?;
+            encoder.write_image(image.as_raw(), width, height, ExtendedColorType::Rgba8)
+        }
+        OutputFormat::Gif => GifEncoder::new_with_speed(writer, GIF_SPEED)
+            .encode_frame(Frame::new(gif_frame(image, config))),
+        OutputFormat::Html => {
+            return writer
+                .write_all(render_html(input).as_bytes())
+                .context("Failed to write html")
+        }
+    }
+    .with_context(|| format!("Failed to encode {}", format.extension()))
+}
+
+/// Encode an animation as an animated PNG, GIF or WebP that loops forever.
+pub fn encode_animation(
+    frames: &Frames,
+    format: OutputFormat,
+    input: &Input,
+    writer: impl Write,
+) -> Result<()> {
+    match format {
+        OutputFormat::Png => write_apng(frames, input, writer),
+        OutputFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(writer, GIF_SPEED);
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .context("Failed to encode gif")?;
+            for frame in frames.iter() {
+                let delay = Delay::from_numer_denom_ms(frame.delay_ms, 1);
+                let image = gif_frame(&frame.image, &input.config);
+                encoder
+                    .encode_frame(Frame::from_parts(image, 0, 0, delay))
+                    .context("Failed to encode gif")?;
+            }
+            Ok(())
+        }
+        OutputFormat::Webp => {
+            write_animated_webp(frames.iter(), frames.dimensions(), &input.config, writer)
+        }
+        other => Err(anyhow!(
+            "Animations can be saved as png, gif or webp, not {}",
+            other.extension()
+        )),
+    }
+}
+
+/// [`encode_image`] into an in-memory buffer.
+pub fn encode_to_vec(image: &RgbaImage, format: OutputFormat, input: &Input) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    encode_image(image, format, input, &mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_to_vec, flatten, output_format};
+    use crate::input::{Blending, OutputFormat};
+    use crate::{Config, Input};
+    use image::{ImageBuffer, ImageFormat, Rgba, RgbaImage};
+    use serde_json::json;
+
+    fn sample() -> RgbaImage {
+        ImageBuffer::from_fn(8, 8, |x, _| {
+            if x < 4 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn format_comes_from_config_then_extension() {
+        let mut config = Config::default();
+        assert_eq!(output_format(&config, Some("a.JPG")), OutputFormat::Jpeg);
+        assert_eq!(output_format(&config, Some("a.txt")), OutputFormat::Png);
+        assert_eq!(output_format(&config, None), OutputFormat::Png);
+
+        config.format = Some(OutputFormat::Avif);
+        assert_eq!(output_format(&config, Some("a.png")), OutputFormat::Avif);
+    }
+
+    #[test]
+    fn flatten_fills_transparency_with_background() {
+        let flattened = flatten(&sample(), Rgba([1, 2, 3, 0]), Blending::Linear);
+
+        assert_eq!(flattened.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(flattened.get_pixel(7, 7), &Rgba([1, 2, 3, 255]));
+    }
+
+    #[test]
+    fn encoders_produce_their_formats() {
+        let input: Input = serde_json::from_value(json!({ "lines": [], "config": {} })).unwrap();
+        for (format, expected) in [
+            (OutputFormat::Png, ImageFormat::Png),
+            (OutputFormat::Jpeg, ImageFormat::Jpeg),
+            (OutputFormat::Webp, ImageFormat::WebP),
+            (OutputFormat::Avif, ImageFormat::Avif),
+            (OutputFormat::Gif, ImageFormat::Gif),
+        ] {
+            let bytes = encode_to_vec(&sample(), format, &input).unwrap();
+            assert_eq!(image::guess_format(&bytes).unwrap(), expected);
+        }
+    }
+
+    #[cfg(feature = "webp-lossy")]
+    #[test]
+    fn animated_webp_keeps_every_delay() {
+        use super::write_animated_webp;
+        use crate::animation::Frame;
+        use image::codecs::webp::WebPDecoder;
+        use image::AnimationDecoder;
+
+        // Distinct images, as libwebp merges repeated frames.
+        let frames = [(0, 40), (1, 40), (2, 900)].map(|(x, delay_ms)| {
+            let mut image = sample();
+            image.put_pixel(x, 0, Rgba([0, 0, 255, 255]));
+            Frame { image, delay_ms }
+        });
+        let mut bytes = Vec::new();
+        write_animated_webp(frames, (8, 8), &Config::default(), &mut bytes).unwrap();
+
+        let decoder = WebPDecoder::new(std::io::Cursor::new(bytes)).unwrap();
+        let delays: Vec<_> = decoder
+            .into_frames()
+            .map(|frame| frame.unwrap().delay().numer_denom_ms())
+            .collect();
+        assert_eq!(delays, [(40, 1), (40, 1), (900, 1)]);
+    }
+}
@@ -0,0 +1,40 @@
+use ab_glyph::FontVec;
+use anyhow::{Context, Result};
+#[cfg(feature = "native")]
+use {
+    anyhow::anyhow,
+    std::path::{Path, PathBuf},
+};
+
+/// Parse a TrueType/OpenType font from memory.
+pub fn font_from_bytes(bytes: Vec<u8>) -> Result<FontVec> {
+    FontVec::try_from_vec(bytes).context("Failed to load font")
+}
+
+/// Location of the bundled font, next to the running executable.
+#[cfg(feature = "native")]
+pub fn font_path() -> Result<PathBuf> {
+    let current_exe = std::env::current_exe().context("Failed to locate generator binary")?;
+    let parent = current_exe
+        .parent()
+        .ok_or_else(|| anyhow!("Generator binary has no parent directory"))?;
+    Ok(parent.join("JetBrainsMono-Regular.ttf"))
+}
+
+/// Load the bundled JetBrains Mono font from [`font_path`].
+#[cfg(feature = "native")]
+pub fn load_font() -> Result<FontVec> {
+    load_font_from(&font_path()?)
+}
+
+/// Load a TrueType/OpenType font from `path`.
+#[cfg(feature = "native")]
+pub fn load_font_from(path: &Path) -> Result<FontVec> {
+    let font_data = std::fs::read(path).with_context(|| {
+        format!(
+            "Font not found at {}. Please reinstall the plugin to download the font.",
+            path.display()
+        )
+    })?;
+    font_from_bytes(font_data)
+}
@@ -0,0 +1,221 @@
+//! Cache of rasterized glyph coverage masks.
+//!
+//! Code snippets reuse a small alphabet thousands of times, so every glyph is
+//! outlined and rasterized once per (glyph, scale, subpixel offset) and
+//! then blitted with the color of the segment it belongs to.
+
+use ab_glyph::{point, Font, GlyphId, PxScale, ScaleFont};
+use image::{GenericImage, Rgba};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::blend::mix;
+use crate::input::Blending;
+
+/// Horizontal positions are quantized to this many steps per pixel.
+pub const SUBPIXEL_STEPS: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    glyph: GlyphId,
+    scale: (u32, u32),
+    subpixel: u8,
+}
+
+/// Coverage values of one rasterized glyph, offset from the pen position.
+#[derive(Debug, Default)]
+pub struct GlyphMask {
+    left: i32,
+    top: i32,
+    width: usize,
+    coverage: Vec<f32>,
+}
+
+/// Split a pen position into whole pixels and a quantized subpixel step.
+pub fn quantize_position(x: f32) -> (i32, u8) {
+    let whole = x.floor();
+    let step = ((x - whole) * SUBPIXEL_STEPS as f32).round() as u8;
+    if step == SUBPIXEL_STEPS {
+        (whole as i32 + 1, 0)
+    } else {
+        (whole as i32, step)
+    }
+}
+
+/// Glyph masks shared between renders. A cache is only valid for the font it
+/// was filled from.
+#[derive(Debug, Default)]
+pub struct GlyphCache {
+    masks: RwLock<HashMap<GlyphKey, Arc<GlyphMask>>>,
+}
+
+impl GlyphCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct masks rasterized so far.
+    pub fn len(&self) -> usize {
+        self.masks.read().map(|masks| masks.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn mask(
+        &self,
+        font: &impl Font,
+        glyph: GlyphId,
+        scale: PxScale,
+        subpixel: u8,
+    ) -> Arc<GlyphMask> {
+        let key = GlyphKey {
+            glyph,
+            scale: (scale.x.to_bits(), scale.y.to_bits()),
+            subpixel,
+        };
+
+        if let Some(mask) = self
+            .masks
+            .read()
+            .ok()
+            .and_then(|masks| masks.get(&key).cloned())
+        {
+            return mask;
+        }
+
+        let mask = Arc::new(rasterize(font, key));
+        match self.masks.write() {
+            Ok(mut masks) => masks.entry(key).or_insert(mask).clone(),
+            Err(_) => mask,
+        }
+    }
+}
+
+fn rasterize(font: &impl Font, key: GlyphKey) -> GlyphMask {
+    let scale = PxScale {
+        x: f32::from_bits(key.scale.0),
+        y: f32::from_bits(key.scale.1),
+    };
+    let ascent = font.as_scaled(scale).ascent();
+    let offset = key.subpixel as f32 / SUBPIXEL_STEPS as f32;
+    let glyph = key
+        .glyph
+        .with_scale_and_position(scale, point(offset, ascent));
+
+    let Some(outline) = font.outline_glyph(glyph) else {
+        return GlyphMask::default();
+    };
+
+    let bounds = outline.px_bounds();
+    let width = bounds.width() as usize;
+    let mut mask = GlyphMask {
+        left: bounds.min.x.round() as i32,
+        top: bounds.min.y.round() as i32,
+        width,
+        coverage: vec![0.0; width * bounds.height() as usize],
+    };
+    outline.draw(|x, y, coverage| {
+        mask.coverage[y as usize * width + x as usize] = coverage.clamp(0.0, 1.0);
+    });
+
+    mask
+}
+
+/// Blend `mask` into `canvas` with its pen position at (`x`, `y`).
+fn blit<C>(canvas: &mut C, mask: &GlyphMask, color: Rgba<u8>, blending: Blending, x: i32, y: i32)
+where
+    C: GenericImage<Pixel = Rgba<u8>>,
+{
+    let (canvas_width, canvas_height) = (canvas.width() as i32, canvas.height() as i32);
+
+    for (row, coverage) in mask.coverage.chunks_exact(mask.width.max(1)).enumerate() {
+        let image_y = y + mask.top + row as i32;
+        if !(0..canvas_height).contains(&image_y) {
+            continue;
+        }
+
+        for (column, &coverage) in coverage.iter().enumerate() {
+            let image_x = x + mask.left + column as i32;
+            if coverage <= 0.0 || !(0..canvas_width).contains(&image_x) {
+                continue;
+            }
+
+            let pixel = canvas.get_pixel(image_x as u32, image_y as u32);
+            let blended = mix(pixel, color, coverage, blending);
+            canvas.put_pixel(image_x as u32, image_y as u32, blended);
+        }
+    }
+}
+
+/// Walk the glyphs of `text`, calling `place` with each glyph and its pen
+/// position relative to the start. Returns the total advance.
+fn layout<F, SF>(scaled_font: &SF, text: &str, mut place: impl FnMut(GlyphId, f32)) -> f32
+where
+    F: Font,
+    SF: ScaleFont<F>,
+{
+    let mut caret = 0.0f32;
+    let mut last = None;
+
+    for c in text.chars() {
+        let glyph = scaled_font.glyph_id(c);
+        if let Some(last) = last {
+            caret += scaled_font.kern(last, glyph);
+        }
+        place(glyph, caret);
+        caret += scaled_font.h_advance(glyph);
+        last = Some(glyph);
+    }
+
+    caret
+}
+
+/// Unrounded width of `text` as laid out by [`draw_text`].
+pub fn text_advance<F, SF>(text: &str, scaled_font: &SF) -> f32
+where
+    F: Font,
+    SF: ScaleFont<F>,
+{
+    layout(scaled_font, text, |_, _| {})
+}
+
+/// Draw `text` with its pen starting at (`x`, `y`), the top-left corner of the
+/// line box. `x` may be fractional; glyphs are rasterized at the nearest
+/// subpixel step through `cache`. Returns the pen position after the text.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_text<C>(
+    canvas: &mut C,
+    cache: &GlyphCache,
+    font: &impl Font,
+    scale: PxScale,
+    color: Rgba<u8>,
+    blending: Blending,
+    x: f32,
+    y: i32,
+    text: &str,
+) -> f32
+where
+    C: GenericImage<Pixel = Rgba<u8>>,
+{
+    let advance = layout(&font.as_scaled(scale), text, |glyph, caret| {
+        let (whole, subpixel) = quantize_position(x + caret);
+        let mask = cache.mask(font, glyph, scale, subpixel);
+        blit(canvas, &mask, color, blending, whole, y);
+    });
+    x + advance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quantize_position;
+
+    #[test]
+    fn positions_round_to_nearest_subpixel_step() {
+        assert_eq!(quantize_position(3.0), (3, 0));
+        assert_eq!(quantize_position(3.3), (3, 1));
+        assert_eq!(quantize_position(3.5), (3, 2));
+        assert_eq!(quantize_position(3.9), (4, 0));
+    }
+}
@@ -0,0 +1,200 @@
+//! HTML output: the snippet as a `<pre>` block with inline-styled spans, for
+//! places that accept HTML but not images. With `html_card` the block is
+//! wrapped to look like the rendered card.
+
+use image::Rgba;
+use std::fmt::Write;
+
+use crate::render::{normalize_span_range, LINE_NUMBER_COLOR_HEX};
+use crate::utils::hex_to_rgba;
+use crate::{Config, Input, Line, Span};
+
+const FONT_FAMILY: &str = "ui-monospace, SFMono-Regular, Menlo, Consolas, monospace";
+
+/// CSS for `color`, dropping the alpha channel when it is opaque.
+fn css_color(color: Rgba<u8>) -> String {
+    let [r, g, b, a] = color.0;
+    if a == 255 {
+        format!("#{r:02x}{g:02x}{b:02x}")
+    } else {
+        format!("rgba({r}, {g}, {b}, {:.3})", a as f32 / 255.0)
+    }
+}
+
+fn escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+/// Inline style for a highlighted span, or `None` when it has no visible effect.
+fn span_style(span: &Span) -> Option<String> {
+    let mut style = Vec::new();
+    if let Some(fg) = &span.fg {
+        style.push(format!("color: {}", css_color(hex_to_rgba(fg))));
+    }
+    if let Some(bg) = &span.bg {
+        style.push(format!("background-color: {}", css_color(hex_to_rgba(bg))));
+    }
+    if span.bold == Some(true) {
+        style.push("font-weight: bold".to_string());
+    }
+    if span.italic == Some(true) {
+        style.push("font-style: italic".to_string());
+    }
+    if span.undercurl == Some(true) {
+        style.push("text-decoration: underline wavy".to_string());
+    } else if span.underline == Some(true) {
+        style.push("text-decoration: underline".to_string());
+    }
+    (!style.is_empty()).then(|| style.join("; "))
+}
+
+/// Write `line`, splitting it at its spans the same way the image renderer does.
+fn write_line(line: &Line, out: &mut String) {
+    let mut last_end = 0;
+    for span in &line.spans {
+        let Some((start, end)) = normalize_span_range(&line.text, span.start, span.end, last_end)
+        else {
+            continue;
+        };
+        escape(&line.text[last_end..start], out);
+        match span_style(span) {
+            Some(style) => {
+                let _ = write!(out, "<span style=\"{style}\">");
+                escape(&line.text[start..end], out);
+                out.push_str("</span>");
+            }
+            None => escape(&line.text[start..end], out),
+        }
+        last_end = end;
+    }
+    escape(&line.text[last_end..], out);
+}
+
+/// Style of the `<pre>` element, including the card when `html_card` is set.
+fn pre_style(config: &Config) -> String {
+    let mut style = format!(
+        "margin: 0; background-color: {}; color: {}; font-family: {FONT_FAMILY}; \
+         font-size: {}px; line-height: {}px",
+        css_color(hex_to_rgba(&config.background)),
+        css_color(hex_to_rgba(&config.foreground)),
+        config.font_size,
+        config.line_height,
+    );
+    if config.html_card {
+        let _ = write!(
+            style,
+            "; display: inline-block; padding: {}px; border-radius: {}px",
+            config.padding, config.border_radius
+        );
+        if config.shadow {
+            style.push_str("; box-shadow: 0 8px 40px rgba(0, 0, 0, 0.5)");
+        }
+    }
+    style
+}
+
+/// Render `input` as a standalone HTML fragment.
+pub fn render_html(input: &Input) -> String {
+    let config = &input.config;
+    let mut out = String::new();
+
+    if config.html_card {
+        // The image reserves three blur radii around the card for the shadow.
+        let shadow_margin = if config.shadow { 60 } else { 0 };
+        let _ = write!(
+            out,
+            "<div style=\"display: inline-block; padding: {}px; background-color: {}; \
+             border-radius: {}px\">",
+            config.outer_padding + shadow_margin,
+            css_color(hex_to_rgba(&config.outer_background)),
+            config.border_radius,
+        );
+    }
+
+    let _ = write!(out, "<pre style=\"{}\"><code>", pre_style(config));
+    let line_number_color = css_color(hex_to_rgba(LINE_NUMBER_COLOR_HEX));
+    for (idx, line) in input.lines.iter().enumerate() {
+        if idx > 0 {
+            out.push('\n');
+        }
+        if config.line_numbers {
+            let _ = write!(
+                out,
+                "<span style=\"color: {line_number_color}; user-select: none\">{:>4}  </span>",
+                config.start_line + idx
+            );
+        }
+        write_line(line, &mut out);
+    }
+    out.push_str("</code></pre>");
+
+    if config.html_card {
+        out.push_str("</div>");
+    }
+    out.push('\n');
+    out
+}
+
+/// The plain text of `input`, offered next to HTML on the clipboard.
+pub fn plain_text(input: &Input) -> String {
+    let lines: Vec<_> = input.lines.iter().map(|line| line.text.as_str()).collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_html;
+    use crate::Input;
+    use serde_json::json;
+
+    fn input(config: serde_json::Value) -> Input {
+        serde_json::from_value(json!({
+            "lines": [
+                { "text": "if a < b {", "spans": [
+                    { "start": 0, "end": 2, "fg": "#c678dd", "bold": true },
+                    { "start": 5, "end": 6, "bg": "#ff000080", "undercurl": true }
+                ] },
+                { "text": "}", "spans": [] }
+            ],
+            "config": config
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn spans_become_styled_and_escaped() {
+        let html = render_html(&input(json!({ "html_card": false })));
+        let code = html
+            .split_once("<code>")
+            .and_then(|(_, rest)| rest.split_once("</code>"))
+            .unwrap()
+            .0;
+
+        assert_eq!(
+            code,
+            "<span style=\"color: #c678dd; font-weight: bold\">if</span> a \
+             <span style=\"background-color: rgba(255, 0, 0, 0.502); \
+             text-decoration: underline wavy\">&lt;</span> b {\n}"
+        );
+        assert!(!html.starts_with("<div"));
+    }
+
+    #[test]
+    fn card_wraps_the_block() {
+        let html = render_html(&input(json!({ "line_numbers": true, "start_line": 9 })));
+
+        assert!(html.starts_with("<div style=\"display: inline-block; padding: 75px"));
+        assert!(html.contains("border-radius: 5px; box-shadow:"));
+        assert!(html.contains("user-select: none\">   9  </span>"));
+        assert!(html.contains("user-select: none\">  10  </span>}"));
+        assert!(html.ends_with("</code></pre></div>\n"));
+    }
+}
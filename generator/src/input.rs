@@ -0,0 +1,394 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A highlighted byte range of a line.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Span {
+    /// Byte offset where the span starts.
+    pub start: usize,
+    /// Byte offset where the span ends (exclusive).
+    pub end: usize,
+    /// Text color in hex format.
+    pub fg: Option<String>,
+    /// Background color in hex format.
+    pub bg: Option<String>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub undercurl: Option<bool>,
+}
+
+/// A single line of source code with its highlights.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Line {
+    pub text: String,
+    pub spans: Vec<Span>,
+}
+
+/// Color space used to blend antialiased text, the shadow and the card edges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Blending {
+    /// Blend 8-bit sRGB values directly, as earlier versions did.
+    #[default]
+    Srgb,
+    /// Blend in linear light, which keeps thin light text fuller and soft
+    /// shadows lighter.
+    Linear,
+}
+
+/// Encoded format of the saved snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+    Gif,
+    /// Highlighted text in a `<pre>` block rather than an image.
+    Html,
+}
+
+/// How a snapshot streamed to stdout (`output_path = "-"`) is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputEncoding {
+    /// The encoded file as is.
+    #[default]
+    Raw,
+    /// Base64 text.
+    Base64,
+    /// A `data:` URI, ready to use in HTML or Markdown.
+    DataUri,
+}
+
+/// Where clipboard copies go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardBackend {
+    /// Try each backend below in order until one succeeds.
+    #[default]
+    Auto,
+    /// The system clipboard: natively on macOS and Windows, through the `arboard` library elsewhere.
+    System,
+    /// The `wl-copy` command on Wayland.
+    WlCopy,
+    /// The `xclip` command on X11.
+    Xclip,
+    /// The `xsel` command on X11, which only copies text.
+    Xsel,
+    /// An OSC 52 escape sequence that asks the terminal to copy text, which also works over SSH.
+    Osc52,
+}
+
+/// What happens when the output file already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Overwrite {
+    /// Replace the existing file.
+    #[default]
+    Always,
+    /// Keep the existing file and fail.
+    Never,
+    /// Save next to it with `-1`, `-2`, … added before the extension.
+    Rename,
+}
+
+/// Unit the typing animation reveals at a time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Reveal {
+    /// Type the code character by character.
+    #[default]
+    Chars,
+    /// Show one more line at a time.
+    Lines,
+}
+
+/// Animation that types the code out, or morphs `before` into it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Animation {
+    /// Reveal "chars" (default) or whole "lines" at a time.
+    #[serde(default)]
+    pub reveal: Reveal,
+    /// Frames per second while typing, from 1 to 100.
+    #[serde(default = "default_fps")]
+    pub fps: u32,
+    /// Characters or lines revealed per second, at least 0.1 (defaults to 30
+    /// characters or 4 lines).
+    #[serde(default)]
+    pub speed: Option<f32>,
+    /// Seconds to hold the finished snippet before the animation loops, at most 60.
+    #[serde(default = "default_hold")]
+    pub hold: f32,
+    /// Draw a cursor after the last revealed character, blinking while holding.
+    #[serde(default = "default_cursor")]
+    pub cursor: bool,
+    /// Seconds the transition from `before` to `lines` takes, at most 60.
+    #[serde(default = "default_duration")]
+    pub duration: f32,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            reveal: Reveal::default(),
+            fps: default_fps(),
+            speed: None,
+            hold: default_hold(),
+            cursor: default_cursor(),
+            duration: default_duration(),
+        }
+    }
+}
+
+fn default_fps() -> u32 {
+    20
+}
+fn default_hold() -> f32 {
+    2.0
+}
+fn default_cursor() -> bool {
+    true
+}
+fn default_duration() -> f32 {
+    1.0
+}
+
+/// Trade-off between PNG encoding time and file size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    /// Maximum deflate level, keeping the smallest of several filter
+    /// strategies. Noticeably slower than `Default` on large snapshots.
+    Best,
+}
+
+/// Rendering and output options.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Config {
+    /// Directory to save snapshots to (defaults to $HOME).
+    #[serde(default)]
+    pub snapshot_dir: Option<String>,
+    /// Path to save the snapshot to (defaults to snapshot_dir/snapshot-{timestamp}.png), or "-" for stdout.
+    #[serde(default)]
+    pub output_path: Option<String>,
+    /// Name of generated files, with placeholders {date}, {time}, {file}, {lang}, {start_line}, {end_line}, {hash} and {ext}.
+    #[serde(default = "default_filename_template")]
+    pub filename_template: String,
+    /// How output to stdout is written: "raw", "base64" or "data_uri". Batch results always carry text.
+    #[serde(default)]
+    pub output_encoding: OutputEncoding,
+    /// What to do when the output file exists: "always" replace it, "never" fail, or "rename" to a numbered name.
+    #[serde(default)]
+    pub overwrite: Overwrite,
+    /// Output format: "png", "jpeg", "webp", "avif", "gif" or "html" (defaults to the output_path extension, else png).
+    #[serde(default)]
+    pub format: Option<OutputFormat>,
+    /// Resolution scale factor for HiDPI/Retina rendering.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Physical resolution written to the image metadata (defaults to 72 × scale).
+    #[serde(default)]
+    pub dpi: Option<f32>,
+    /// Padding around the code in pixels.
+    #[serde(default = "default_padding")]
+    pub padding: u32,
+    /// Height of each line in pixels.
+    #[serde(default = "default_line_height")]
+    pub line_height: f32,
+    /// Font size in pixels.
+    #[serde(default = "default_font_size")]
+    pub font_size: f32,
+    /// Background color of the code card in hex format.
+    #[serde(default = "default_background")]
+    pub background: String,
+    /// Default text color in hex format.
+    #[serde(default = "default_foreground")]
+    pub foreground: String,
+    /// Copy the snapshot to the clipboard.
+    #[serde(default = "default_clipboard")]
+    pub clipboard: bool,
+    /// Save the snapshot to a file. When false, only the clipboard gets it, and the file is
+    /// still saved if copying fails or only copies the plain text.
+    #[serde(default = "default_save")]
+    pub save: bool,
+    /// Clipboard backend: "auto", "system", "wl_copy", "xclip", "xsel" or "osc52".
+    #[serde(default)]
+    pub clipboard_backend: ClipboardBackend,
+    /// Draw a drop shadow behind the code card.
+    #[serde(default = "default_shadow")]
+    pub shadow: bool,
+    /// Show line numbers.
+    #[serde(default = "default_line_numbers")]
+    pub line_numbers: bool,
+    /// Number of the first line.
+    #[serde(default = "default_start_line")]
+    pub start_line: usize,
+    /// Corner radius in pixels.
+    #[serde(default = "default_border_radius")]
+    pub border_radius: u32,
+    /// Background color outside the code card in hex format.
+    #[serde(default = "default_outer_background")]
+    pub outer_background: String,
+    /// Padding between the code card and the outer background edge in pixels.
+    #[serde(default = "default_outer_padding")]
+    pub outer_padding: u32,
+    /// Blending color space: "srgb" (default) or "linear" for fuller light text.
+    #[serde(default)]
+    pub blending: Blending,
+    /// Quality from 1 to 100 for JPEG, lossy WebP and AVIF.
+    #[serde(default = "default_quality")]
+    pub quality: u8,
+    /// Encode WebP losslessly. Lossy WebP needs a generator built with the `webp-lossy` feature.
+    #[serde(default = "default_webp_lossless")]
+    pub webp_lossless: bool,
+    /// AVIF encoder speed from 1 (slowest, smallest) to 10 (fastest).
+    #[serde(default = "default_avif_speed")]
+    pub avif_speed: u8,
+    /// PNG compression effort: "fast", "default" (default) or the slower "best".
+    #[serde(default)]
+    pub png_compression: PngCompression,
+    /// Embed the source text, language, file path and line range in PNG metadata.
+    /// Off by default, as shared images would otherwise carry code and paths.
+    #[serde(default)]
+    pub embed_source: bool,
+    /// Wrap HTML output in a styled card with padding, rounded corners and shadow.
+    #[serde(default = "default_html_card")]
+    pub html_card: bool,
+    /// Save an animation instead of a still image: typing, or a transition from `before`.
+    #[serde(default)]
+    pub animation: Option<Animation>,
+    /// Treat invalid values and unknown keys as errors instead of warnings.
+    #[serde(default)]
+    pub strict: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            snapshot_dir: None,
+            output_path: None,
+            filename_template: default_filename_template(),
+            output_encoding: OutputEncoding::default(),
+            overwrite: Overwrite::default(),
+            format: None,
+            scale: default_scale(),
+            dpi: None,
+            padding: default_padding(),
+            line_height: default_line_height(),
+            font_size: default_font_size(),
+            background: default_background(),
+            foreground: default_foreground(),
+            clipboard: default_clipboard(),
+            save: default_save(),
+            clipboard_backend: ClipboardBackend::default(),
+            shadow: default_shadow(),
+            line_numbers: default_line_numbers(),
+            start_line: default_start_line(),
+            border_radius: default_border_radius(),
+            outer_background: default_outer_background(),
+            outer_padding: default_outer_padding(),
+            blending: Blending::default(),
+            quality: default_quality(),
+            webp_lossless: default_webp_lossless(),
+            avif_speed: default_avif_speed(),
+            png_compression: PngCompression::default(),
+            embed_source: false,
+            html_card: default_html_card(),
+            animation: None,
+            strict: false,
+        }
+    }
+}
+
+fn default_filename_template() -> String {
+    "snapshot-{date}_{time}.{ext}".to_string()
+}
+fn default_padding() -> u32 {
+    25
+}
+fn default_scale() -> f32 {
+    2.0
+}
+fn default_line_height() -> f32 {
+    28.0
+}
+fn default_font_size() -> f32 {
+    24.0
+}
+fn default_background() -> String {
+    "#282c34".to_string()
+}
+fn default_foreground() -> String {
+    "#abb2bf".to_string()
+}
+fn default_save() -> bool {
+    true
+}
+
+fn default_clipboard() -> bool {
+    true
+}
+fn default_shadow() -> bool {
+    true
+}
+fn default_line_numbers() -> bool {
+    false
+}
+fn default_start_line() -> usize {
+    1
+}
+fn default_border_radius() -> u32 {
+    5
+}
+fn default_outer_background() -> String {
+    "#ffffff".to_string()
+}
+fn default_outer_padding() -> u32 {
+    15
+}
+fn default_quality() -> u8 {
+    90
+}
+fn default_webp_lossless() -> bool {
+    true
+}
+fn default_avif_speed() -> u8 {
+    6
+}
+fn default_html_card() -> bool {
+    true
+}
+
+/// Where the snippet was taken from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Source {
+    /// Language of the code, e.g. the editor filetype.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Path of the file the code was taken from.
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Generator input as sent by the frontend on stdin.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Input {
+    /// Version of the input protocol, see `snapshot-generator --capabilities`.
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    pub lines: Vec<Line>,
+    pub config: Config,
+    /// Earlier version of `lines`. With `config.animation` set, the animation
+    /// morphs these into `lines` instead of typing them.
+    #[serde(default)]
+    pub before: Option<Vec<Line>>,
+    /// Origin of the code, embedded in PNG metadata.
+    #[serde(default)]
+    pub source: Option<Source>,
+}
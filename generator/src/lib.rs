@@ -0,0 +1,49 @@
+//! Renderer behind the `snapshot-generator` binary.
+//!
+//! Build an [`Input`] (or deserialize one from the JSON the Neovim plugin
+//! sends) and pass it to [`render`] to get the finished snapshot image.
+
+pub mod animation;
+#[cfg(feature = "native")]
+pub mod batch;
+pub mod blend;
+#[cfg(feature = "native")]
+mod clipboard;
+pub mod encode;
+mod font;
+pub mod glyphs;
+pub mod html;
+mod input;
+#[cfg(feature = "lua")]
+mod lua;
+pub mod metadata;
+#[cfg(feature = "native")]
+mod output;
+mod png_writer;
+#[cfg(feature = "native")]
+pub mod preview;
+pub mod protocol;
+mod render;
+pub mod schema;
+pub mod shadow;
+mod transition;
+pub mod utils;
+pub mod validate;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "native")]
+pub use clipboard::{copy_html_to_clipboard, copy_to_clipboard, Copied};
+pub use font::font_from_bytes;
+#[cfg(feature = "native")]
+pub use font::{font_path, load_font, load_font_from};
+pub use glyphs::GlyphCache;
+pub use input::{
+    Animation, Blending, ClipboardBackend, Config, Input, Line, OutputEncoding, OutputFormat,
+    Overwrite, PngCompression, Reveal, Source, Span,
+};
+#[cfg(feature = "native")]
+pub use output::{resolve_output_path, save_animation, save_html, save_image, STDOUT_PATH};
+#[cfg(feature = "native")]
+pub use render::render;
+pub use render::{render_with_cache, render_with_font};
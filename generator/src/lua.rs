@@ -0,0 +1,178 @@
+//! Lua C module exposing the renderer to Neovim without spawning a process.
+//!
+//! Built with `cargo build --release --features lua`, then loaded from Lua:
+//!
+//! ```lua
+//! local generator = package.loadlib(path, "luaopen_snapshot_generator")()
+//! generator.load_font(font_path)
+//! local path, warnings = generator.render(lines, config, source, before)
+//! ```
+
+use ab_glyph::FontVec;
+use image::RgbaImage;
+use mlua::{Lua, LuaSerdeExt, Result, Table, Value};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::animation::Frames;
+use crate::encode::{encode_animation, encode_to_vec, output_format};
+use crate::html::render_html;
+use crate::protocol::capabilities;
+use crate::validate::{enforce_with, take_invalid_numbers, validate_input};
+use crate::{
+    copy_html_to_clipboard, copy_to_clipboard, load_font_from, render_with_cache,
+    resolve_output_path, save_animation, save_image, GlyphCache, Input, OutputFormat, STDOUT_PATH,
+};
+
+/// Font loaded by `load_font`, with the glyphs rasterized from it so far.
+struct LoadedFont {
+    font: FontVec,
+    glyphs: GlyphCache,
+}
+
+static FONT: Mutex<Option<Arc<LoadedFont>>> = Mutex::new(None);
+
+fn lua_error(error: anyhow::Error) -> mlua::Error {
+    mlua::Error::runtime(format!("{error:#}"))
+}
+
+fn font() -> Result<Arc<LoadedFont>> {
+    FONT.lock()
+        .map_err(|_| mlua::Error::runtime("Font cache is poisoned"))?
+        .clone()
+        .ok_or_else(|| mlua::Error::runtime("No font loaded, call load_font(path) first"))
+}
+
+/// Build an `Input` from Lua tables, returning validation warnings alongside it.
+fn input_from_lua(
+    lua: &Lua,
+    lines: Value,
+    config: Value,
+    source: Value,
+    before: Value,
+) -> Result<(Input, Vec<String>)> {
+    let lines = lua.from_value(lines)?;
+    let source = lua.from_value(source)?;
+    let before = lua.from_value(before)?;
+    let raw_config: serde_json::Value = lua.from_value(config)?;
+    let raw = json!({ "config": raw_config });
+    let mut checked = raw.clone();
+    let invalid = take_invalid_numbers(&mut checked);
+    let config = crate::Config::deserialize(&checked["config"]).map_err(mlua::Error::external)?;
+
+    let input = Input {
+        protocol_version: None,
+        lines,
+        config,
+        before,
+        source,
+    };
+    let issues = validate_input(&input, &raw);
+    let warnings = enforce_with(invalid, issues, input.config.strict)
+        .map_err(lua_error)?
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    Ok((input, warnings))
+}
+
+/// `lines, config[, source[, before]]` as passed from Lua.
+type Args = (Value, Value, Value, Value);
+
+/// Parse the arguments and render the still image, returning the font too so
+/// animations can render their frames with it.
+fn render_input(
+    lua: &Lua,
+    (lines, config, source, before): Args,
+) -> Result<(Input, Arc<LoadedFont>, RgbaImage, Vec<String>)> {
+    let (input, warnings) = input_from_lua(lua, lines, config, source, before)?;
+    let loaded = font()?;
+    let image = render_with_cache(&input, &loaded.font, &loaded.glyphs);
+    Ok((input, loaded, image, warnings))
+}
+
+fn load_font(_: &Lua, path: String) -> Result<()> {
+    let font = load_font_from(Path::new(&path)).map_err(lua_error)?;
+    *FONT
+        .lock()
+        .map_err(|_| mlua::Error::runtime("Font cache is poisoned"))? =
+        Some(Arc::new(LoadedFont {
+            font,
+            glyphs: GlyphCache::new(),
+        }));
+    Ok(())
+}
+
+/// Render, copy to the clipboard if configured, save, and return the saved path
+/// (nil when `save = false` left the snapshot on the clipboard only) plus a
+/// list of warnings.
+fn render(lua: &Lua, args: Args) -> Result<(Option<String>, Vec<String>)> {
+    let (input, loaded, image, mut warnings) = render_input(lua, args)?;
+
+    let output_path = resolve_output_path(&input);
+    if output_path == STDOUT_PATH {
+        return Err(mlua::Error::runtime(
+            "output_path \"-\" would write into Neovim's stdout, use render_png instead",
+        ));
+    }
+    let html = output_format(&input.config, Some(&output_path)) == OutputFormat::Html;
+
+    let mut copied = None;
+    if input.config.clipboard {
+        let copy = if html {
+            copy_html_to_clipboard(&render_html(&input), &input)
+        } else {
+            copy_to_clipboard(&image, &input)
+        };
+        match copy {
+            Ok(outcome) => copied = Some(outcome),
+            Err(error) => warnings.push(error.to_string()),
+        }
+    }
+    if !input.config.save {
+        match copied.map(|copied| copied.text_only_warning()) {
+            Some(None) => return Ok((None, warnings)),
+            Some(Some(warning)) => warnings.push(warning),
+            None => {
+                warnings.push("the snapshot is not on the clipboard, saving it instead".to_string())
+            }
+        }
+    }
+
+    let saved_path = if input.config.animation.is_some() {
+        let frames = Frames::new(&input, &loaded.font, &loaded.glyphs);
+        save_animation(&frames, &output_path, &input)
+    } else {
+        save_image(&image, &output_path, &input)
+    }
+    .map_err(lua_error)?;
+    Ok((Some(saved_path), warnings))
+}
+
+/// Render and return the PNG-encoded image (an APNG for animations) as a Lua
+/// string, plus warnings.
+fn render_png(lua: &Lua, args: Args) -> Result<(mlua::String, Vec<String>)> {
+    let (input, loaded, image, warnings) = render_input(lua, args)?;
+
+    let bytes = if input.config.animation.is_some() {
+        let frames = Frames::new(&input, &loaded.font, &loaded.glyphs);
+        let mut bytes = Vec::new();
+        encode_animation(&frames, OutputFormat::Png, &input, &mut bytes).map(|()| bytes)
+    } else {
+        encode_to_vec(&image, OutputFormat::Png, &input)
+    }
+    .map_err(lua_error)?;
+    Ok((lua.create_string(bytes)?, warnings))
+}
+
+#[mlua::lua_module]
+fn snapshot_generator(lua: &Lua) -> Result<Table> {
+    let exports = lua.create_table()?;
+    exports.set("load_font", lua.create_function(load_font)?)?;
+    exports.set("render", lua.create_function(render)?)?;
+    exports.set("render_png", lua.create_function(render_png)?)?;
+    exports.set("capabilities", lua.to_value(&capabilities())?)?;
+    Ok(exports)
+}
@@ -0,0 +1,293 @@
+//! Metadata written alongside the pixels: the source text and provenance
+//! embedded in PNG iTXt chunks, so the code shown in a shared snapshot can be
+//! recovered with `snapshot-generator extract`, and the physical resolution
+//! that makes a 2x render display at its logical size.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::io::Cursor;
+
+use crate::render::render_scale;
+use crate::{Config, Input};
+
+const CODE_KEY: &str = "snapshot.code";
+const LANGUAGE_KEY: &str = "snapshot.language";
+const PATH_KEY: &str = "snapshot.path";
+const LINES_KEY: &str = "snapshot.lines";
+const SOFTWARE_KEY: &str = "Software";
+
+/// Resolution of a snapshot rendered at scale 1.
+const BASE_DPI: f32 = 72.0;
+
+/// One iTXt chunk. Large values are stored compressed.
+pub struct TextChunk {
+    pub keyword: &'static str,
+    pub text: String,
+    pub compressed: bool,
+}
+
+/// Source information recovered from a snapshot.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct EmbeddedSource {
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+}
+
+fn chunk(keyword: &'static str, text: impl Into<String>) -> TextChunk {
+    TextChunk {
+        keyword,
+        text: text.into(),
+        compressed: false,
+    }
+}
+
+fn generator_version() -> String {
+    format!("snapshot.nvim {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Chunks describing `input`. Only the generator version is written when
+/// `embed_source` is off.
+pub fn text_chunks(input: &Input) -> Vec<TextChunk> {
+    let mut chunks = vec![chunk(SOFTWARE_KEY, generator_version())];
+    if !input.config.embed_source {
+        return chunks;
+    }
+
+    let code: Vec<_> = input.lines.iter().map(|line| line.text.as_str()).collect();
+    chunks.push(TextChunk {
+        compressed: true,
+        ..chunk(CODE_KEY, code.join("\n"))
+    });
+
+    let source = input.source.clone().unwrap_or_default();
+    if let Some(language) = source.language.filter(|language| !language.is_empty()) {
+        chunks.push(chunk(LANGUAGE_KEY, language));
+    }
+    if let Some(path) = source.path.filter(|path| !path.is_empty()) {
+        chunks.push(chunk(PATH_KEY, path));
+    }
+    if !input.lines.is_empty() {
+        let start = input.config.start_line;
+        let end = start.saturating_add(input.lines.len() - 1);
+        chunks.push(chunk(LINES_KEY, format!("{start}-{end}")));
+    }
+
+    chunks
+}
+
+/// Dots per inch to record for `config`: the configured `dpi`, else 72 × scale.
+pub fn dpi(config: &Config) -> f32 {
+    config
+        .dpi
+        .filter(|dpi| dpi.is_finite() && *dpi > 0.0)
+        .unwrap_or_else(|| BASE_DPI * render_scale(config.scale))
+}
+
+/// Minimal little-endian EXIF (TIFF) block holding only the X/Y resolution in
+/// pixels per inch, as stored by WebP and AVIF.
+pub fn exif_resolution(dpi: f32) -> Vec<u8> {
+    const RATIONAL: u16 = 5;
+    const SHORT: u16 = 3;
+    const INCHES: u32 = 2;
+    // Header, then an IFD with three entries, then the two rationals it points to.
+    const IFD_OFFSET: u32 = 8;
+    const DATA_OFFSET: u32 = IFD_OFFSET + 2 + 3 * 12 + 4;
+
+    let numerator = (dpi * 100.0).round().clamp(1.0, u32::MAX as f32) as u32;
+    let mut exif = Vec::with_capacity(DATA_OFFSET as usize + 16);
+    exif.extend_from_slice(b"II");
+    exif.extend_from_slice(&42u16.to_le_bytes());
+    exif.extend_from_slice(&IFD_OFFSET.to_le_bytes());
+
+    exif.extend_from_slice(&3u16.to_le_bytes());
+    let mut entry = |tag: u16, kind: u16, value: u32| {
+        exif.extend_from_slice(&tag.to_le_bytes());
+        exif.extend_from_slice(&kind.to_le_bytes());
+        exif.extend_from_slice(&1u32.to_le_bytes());
+        exif.extend_from_slice(&value.to_le_bytes());
+    };
+    entry(0x011a, RATIONAL, DATA_OFFSET);
+    entry(0x011b, RATIONAL, DATA_OFFSET + 8);
+    entry(0x0128, SHORT, INCHES);
+    exif.extend_from_slice(&0u32.to_le_bytes());
+
+    for _ in 0..2 {
+        exif.extend_from_slice(&numerator.to_le_bytes());
+        exif.extend_from_slice(&100u32.to_le_bytes());
+    }
+    exif
+}
+
+/// Add an EXIF chunk to a WebP file, converting it to the extended format if
+/// needed.
+pub fn add_webp_exif(webp: &[u8], width: u32, height: u32, exif: &[u8]) -> Result<Vec<u8>> {
+    if webp.len() < 12 || &webp[..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return Err(anyhow!("Not a WebP file"));
+    }
+    const EXIF_FLAG: u8 = 0x08;
+
+    let chunks = &webp[12..];
+    let mut body = b"WEBP".to_vec();
+    if chunks.starts_with(b"VP8X") {
+        body.extend_from_slice(chunks);
+        body[12] |= EXIF_FLAG;
+    } else {
+        body.extend_from_slice(b"VP8X");
+        body.extend_from_slice(&10u32.to_le_bytes());
+        body.extend_from_slice(&[EXIF_FLAG, 0, 0, 0]);
+        body.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        body.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        body.extend_from_slice(chunks);
+    }
+
+    body.extend_from_slice(b"EXIF");
+    body.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+    body.extend_from_slice(exif);
+    if exif.len() % 2 == 1 {
+        body.push(0);
+    }
+
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    file.extend_from_slice(&body);
+    Ok(file)
+}
+
+/// Read the source embedded in a PNG written by the generator.
+pub fn read_embedded_source(bytes: &[u8]) -> Result<EmbeddedSource> {
+    let reader = png::Decoder::new(Cursor::new(bytes))
+        .read_info()
+        .context("Failed to read PNG")?;
+
+    let mut source = EmbeddedSource::default();
+    let mut found_code = false;
+    for chunk in &reader.info().utf8_text {
+        let text = chunk.get_text().context("Failed to read PNG text chunk")?;
+        match chunk.keyword.as_str() {
+            CODE_KEY => {
+                source.code = text;
+                found_code = true;
+            }
+            LANGUAGE_KEY => source.language = Some(text),
+            PATH_KEY => source.path = Some(text),
+            LINES_KEY => {
+                if let Some((start, end)) = text.split_once('-') {
+                    source.start_line = start.parse().ok();
+                    source.end_line = end.parse().ok();
+                }
+            }
+            SOFTWARE_KEY => source.generator = Some(text),
+            _ => {}
+        }
+    }
+
+    if !found_code {
+        return Err(anyhow!(
+            "No embedded source code found (the snapshot was saved without embed_source = true, \
+             converted to another format, or not made by snapshot.nvim)"
+        ));
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_webp_exif, dpi, exif_resolution, read_embedded_source, text_chunks, EmbeddedSource,
+    };
+    use crate::encode::encode_to_vec;
+    use crate::input::{OutputFormat, Source};
+    use crate::{Config, Input, Line};
+    use image::{ImageBuffer, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    fn input(embed_source: bool) -> Input {
+        let line = |text: &str| Line {
+            text: text.to_string(),
+            spans: Vec::new(),
+        };
+        Input {
+            protocol_version: None,
+            lines: vec![line("fn main() {"), line("    println!(\"π\");"), line("}")],
+            config: Config {
+                start_line: 10,
+                embed_source,
+                ..Config::default()
+            },
+            before: None,
+            source: Some(Source {
+                language: Some("rust".to_string()),
+                path: Some("src/main.rs".to_string()),
+            }),
+        }
+    }
+
+    fn image() -> RgbaImage {
+        ImageBuffer::from_pixel(4, 4, Rgba([40, 44, 52, 255]))
+    }
+
+    #[test]
+    fn embedded_source_round_trips() {
+        let bytes = encode_to_vec(&image(), OutputFormat::Png, &input(true)).unwrap();
+
+        assert_eq!(
+            read_embedded_source(&bytes).unwrap(),
+            EmbeddedSource {
+                code: "fn main() {\n    println!(\"π\");\n}".to_string(),
+                language: Some("rust".to_string()),
+                path: Some("src/main.rs".to_string()),
+                start_line: Some(10),
+                end_line: Some(12),
+                generator: Some(format!("snapshot.nvim {}", env!("CARGO_PKG_VERSION"))),
+            }
+        );
+    }
+
+    #[test]
+    fn disabling_embed_source_keeps_only_the_version() {
+        let chunks = text_chunks(&input(false));
+        assert_eq!(chunks.len(), 1);
+
+        let bytes = encode_to_vec(&image(), OutputFormat::Png, &input(false)).unwrap();
+        assert!(read_embedded_source(&bytes).is_err());
+    }
+
+    #[test]
+    fn dpi_defaults_to_72_per_unit_of_scale() {
+        let mut config = Config {
+            scale: 2.0,
+            ..Config::default()
+        };
+        assert_eq!(dpi(&config), 144.0);
+
+        config.dpi = Some(300.0);
+        assert_eq!(dpi(&config), 300.0);
+    }
+
+    #[test]
+    fn webp_exif_is_readable() {
+        use image::codecs::webp::{WebPDecoder, WebPEncoder};
+        use image::{ExtendedColorType, ImageDecoder, ImageEncoder};
+
+        let exif = exif_resolution(144.0);
+        let mut plain = Vec::new();
+        WebPEncoder::new_lossless(&mut plain)
+            .write_image(image().as_raw(), 4, 4, ExtendedColorType::Rgba8)
+            .unwrap();
+        let encoded = encode_to_vec(&image(), OutputFormat::Webp, &input(true)).unwrap();
+
+        for webp in [encoded, add_webp_exif(&plain, 4, 4, &exif).unwrap()] {
+            let mut decoder = WebPDecoder::new(Cursor::new(webp)).unwrap();
+            assert_eq!(decoder.exif_metadata().unwrap(), Some(exif.clone()));
+        }
+    }
+}
@@ -0,0 +1,465 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::offset::Local;
+use chrono::DateTime;
+use image::RgbaImage;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::animation::Frames;
+use crate::encode::{encode_animation, encode_image, output_format};
+use crate::input::{OutputEncoding, OutputFormat, Overwrite};
+use crate::utils::{base64, fill_template};
+use crate::Input;
+
+/// `output_path` that streams the snapshot to stdout instead of a file.
+pub const STDOUT_PATH: &str = "-";
+
+/// `bytes` as text: a data URI when asked for, else base64.
+pub fn encode_text(bytes: &[u8], format: OutputFormat, encoding: OutputEncoding) -> String {
+    match encoding {
+        OutputEncoding::DataUri => format!("data:{};base64,{}", format.mime_type(), base64(bytes)),
+        OutputEncoding::Raw | OutputEncoding::Base64 => base64(bytes),
+    }
+}
+
+/// Write the snapshot to stdout in the configured `output_encoding`. Text
+/// encodings end with a newline.
+fn write_stdout(
+    input: &Input,
+    encode: impl FnOnce(OutputFormat, &mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let format = output_format(&input.config, None);
+    let mut stdout = io::stdout().lock();
+    match input.config.output_encoding {
+        OutputEncoding::Raw => {
+            let mut writer = BufWriter::new(stdout);
+            encode(format, &mut writer)?;
+            writer.flush().context("Failed to write to stdout")
+        }
+        encoding => {
+            let mut bytes = Vec::new();
+            encode(format, &mut bytes)?;
+            writeln!(stdout, "{}", encode_text(&bytes, format, encoding))
+                .context("Failed to write to stdout")
+        }
+    }
+}
+
+/// Keep a placeholder value from adding directories or odd characters to the file name.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Short FNV-1a hash of the code, stable across runs and platforms.
+fn content_hash(input: &Input) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (idx, line) in input.lines.iter().enumerate() {
+        let separator = if idx > 0 { &b"\n"[..] } else { &[] };
+        for &byte in separator.iter().chain(line.text.as_bytes()) {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:08x}", hash >> 32)
+}
+
+/// `filename_template` filled in for `input` at time `now`.
+fn file_name(input: &Input, now: DateTime<Local>) -> String {
+    let config = &input.config;
+    let source = input.source.clone().unwrap_or_default();
+    fill_template(&config.filename_template, |name| {
+        Some(match name {
+            "date" => now.format("%Y-%m-%d").to_string(),
+            "time" => now.format("%H-%M-%S").to_string(),
+            "file" => source
+                .path
+                .as_deref()
+                .and_then(|path| Path::new(path).file_stem()?.to_str())
+                .map_or_else(|| "snippet".to_string(), sanitize),
+            "lang" => source
+                .language
+                .as_deref()
+                .filter(|language| !language.is_empty())
+                .map_or_else(|| "text".to_string(), sanitize),
+            "start_line" => config.start_line.to_string(),
+            "end_line" => (config.start_line + input.lines.len().saturating_sub(1)).to_string(),
+            "hash" => content_hash(input),
+            "ext" => output_format(config, None).extension().to_string(),
+            _ => return None,
+        })
+    })
+}
+
+/// `path`, or the first of `name-1.ext`, `name-2.ext`, … that does not exist yet.
+fn unique_path(path: String) -> String {
+    let exists = |path: &str| {
+        shellexpand::full(path).is_ok_and(|expanded| Path::new(expanded.as_ref()).exists())
+    };
+    if !exists(&path) {
+        return path;
+    }
+
+    (1..)
+        .map(|suffix| numbered(&path, suffix))
+        .find(|candidate| !exists(candidate))
+        .expect("some suffix is free")
+}
+
+/// `path` with `-suffix` before its extension.
+fn numbered(path: &str, suffix: usize) -> String {
+    let (stem, extension) = match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => path.split_at(dot),
+        _ => (path, ""),
+    };
+    format!("{stem}-{suffix}{extension}")
+}
+
+/// Explicit `output_path`, or `filename_template` in `snapshot_dir` (or
+/// `$HOME`), numbered when that file already exists.
+pub fn resolve_output_path(input: &Input) -> String {
+    let config = &input.config;
+    if let Some(path) = &config.output_path {
+        return path.clone();
+    }
+
+    let filename = file_name(input, Local::now());
+    let dir = config
+        .snapshot_dir
+        .clone()
+        .unwrap_or_else(|| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+    unique_path(format!("{dir}/{filename}"))
+}
+
+/// Expand `~` and env vars in `output_path`, apply the `overwrite` policy,
+/// create parent directories and write the file with `encode`. Returns the
+/// path written, or `-` after writing to stdout.
+pub fn save_with(
+    output_path: &str,
+    input: &Input,
+    encode: impl FnOnce(OutputFormat, &mut dyn Write) -> Result<()>,
+) -> Result<String> {
+    if output_path == STDOUT_PATH {
+        write_stdout(input, encode)?;
+        return Ok(STDOUT_PATH.to_string());
+    }
+
+    let expanded_path = shellexpand::full(output_path)
+        .context("Failed to expand output path")?
+        .to_string();
+
+    if let Some(parent) = Path::new(&expanded_path).parent() {
+        std::fs::create_dir_all(parent).context("Failed to create parent directories")?;
+    }
+
+    let format = output_format(&input.config, Some(&expanded_path));
+    write_atomically(&expanded_path, input.config.overwrite, |writer| {
+        encode(format, writer)
+    })
+}
+
+/// Move the finished `temp` file to `path`, or a numbered name next to it, as
+/// `overwrite` asks, and return the path used. Only `Always` replaces a file;
+/// the other policies hard-link the file into place, which fails instead of
+/// replacing a file that appeared since the output path was chosen.
+fn publish(temp: &Path, path: &str, overwrite: Overwrite) -> Result<String> {
+    publish_with(temp, path, overwrite, |temp, path| {
+        std::fs::hard_link(temp, path)
+    })
+}
+
+/// [`publish`] with `hard_link` standing in for [`std::fs::hard_link`]. Where
+/// linking fails for another reason than an existing file, as on FAT, some
+/// network shares and Android storage, `temp` is copied into a newly created
+/// file instead, which fails the same way.
+fn publish_with(
+    temp: &Path,
+    path: &str,
+    overwrite: Overwrite,
+    hard_link: impl Fn(&Path, &str) -> io::Result<()>,
+) -> Result<String> {
+    let link = |candidate: &str| match hard_link(temp, candidate) {
+        Err(error) if error.kind() != io::ErrorKind::AlreadyExists => copy_new(temp, candidate),
+        linked => linked,
+    };
+    let exists = |error: &io::Error| error.kind() == io::ErrorKind::AlreadyExists;
+    match overwrite {
+        Overwrite::Always => std::fs::rename(temp, path).map(|()| path.to_string()),
+        Overwrite::Never => match link(path) {
+            Err(error) if exists(&error) => {
+                return Err(anyhow!(
+                    "{path} already exists, set overwrite = \"always\" or \"rename\" to save anyway"
+                ));
+            }
+            linked => linked.map(|()| path.to_string()),
+        },
+        Overwrite::Rename => std::iter::once(path.to_string())
+            .chain((1..).map(|suffix| numbered(path, suffix)))
+            .map(|candidate| link(&candidate).map(|()| candidate))
+            .find(|linked| !linked.as_ref().is_err_and(exists))
+            .expect("some suffix is free"),
+    }
+    .context("Failed to save image")
+}
+
+/// Copy `temp` into a file created at `path`, failing if `path` exists.
+fn copy_new(temp: &Path, path: &str) -> io::Result<()> {
+    let mut target = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let copied = io::copy(&mut File::open(temp)?, &mut target).and_then(|_| target.sync_all());
+    if copied.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    copied
+}
+
+/// Write `path` through a temporary file in the same directory that is then
+/// published under the `overwrite` policy, so a crash never leaves a truncated
+/// file behind. Returns the path written.
+fn write_atomically(
+    path: &str,
+    overwrite: Overwrite,
+    encode: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<String> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = Path::new(path)
+        .file_name()
+        .ok_or_else(|| anyhow!("{path} is not a file path"))?;
+    let temp = Path::new(path).with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let file = File::create(&temp).context("Failed to save image")?;
+        let mut writer = BufWriter::new(file);
+        encode(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())
+            .and_then(|file| file.sync_all())
+            .context("Failed to save image")?;
+        publish(&temp, path, overwrite)
+    })();
+    // Renamed away under `Always`; linked or copied into place otherwise.
+    let _ = std::fs::remove_file(&temp);
+    result
+}
+
+/// Encode and save `image` to `output_path`, expanding `~` and env vars and
+/// creating parent directories. Returns the expanded path.
+pub fn save_image(image: &RgbaImage, output_path: &str, input: &Input) -> Result<String> {
+    save_with(output_path, input, |format, writer| {
+        encode_image(image, format, input, writer)
+    })
+}
+
+/// Save HTML produced by [`crate::html::render_html`] to `output_path`, like
+/// [`save_image`].
+pub fn save_html(html: &str, output_path: &str, input: &Input) -> Result<String> {
+    save_with(output_path, input, |_, writer| {
+        writer
+            .write_all(html.as_bytes())
+            .context("Failed to save html")
+    })
+}
+
+/// Like [`save_image`] for an animation.
+pub fn save_animation(frames: &Frames, output_path: &str, input: &Input) -> Result<String> {
+    save_with(output_path, input, |format, writer| {
+        encode_animation(frames, format, input, writer)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_text, file_name, publish, publish_with, resolve_output_path, save_with};
+    use crate::input::{OutputEncoding, OutputFormat, Overwrite, Source};
+    use crate::{Config, Input, Line};
+    use chrono::{Local, TimeZone};
+
+    fn input(config: Config) -> Input {
+        let line = |text: &str| Line {
+            text: text.to_string(),
+            spans: Vec::new(),
+        };
+        Input {
+            protocol_version: None,
+            lines: vec![line("fn main() {"), line("}")],
+            config,
+            before: None,
+            source: Some(Source {
+                language: Some("rust".to_string()),
+                path: Some("src/my main.rs".to_string()),
+            }),
+        }
+    }
+
+    #[test]
+    fn output_path_prefers_explicit_value() {
+        let config = Config {
+            output_path: Some("/tmp/out.png".to_string()),
+            ..Config::default()
+        };
+
+        assert_eq!(resolve_output_path(&input(config)), "/tmp/out.png");
+    }
+
+    #[test]
+    fn generated_path_uses_format_extension() {
+        let config = Config {
+            snapshot_dir: Some("/tmp".to_string()),
+            format: Some(OutputFormat::Webp),
+            ..Config::default()
+        };
+
+        let path = resolve_output_path(&input(config));
+        assert!(path.starts_with("/tmp/snapshot-"), "{path}");
+        assert!(path.ends_with(".webp"), "{path}");
+    }
+
+    #[test]
+    fn filename_template_fills_placeholders() {
+        let now = Local.with_ymd_and_hms(2024, 3, 9, 14, 5, 7).unwrap();
+        let default = file_name(&input(Config::default()), now);
+        assert_eq!(default, "snapshot-2024-03-09_14-05-07.png");
+
+        let config = Config {
+            filename_template: "{lang}/{file}-{start_line}-{end_line}-{hash}.{ext}".to_string(),
+            start_line: 41,
+            ..Config::default()
+        };
+        let name = file_name(&input(config), now);
+        let hash = name.rsplit('-').next().unwrap();
+        assert!(name.starts_with("rust/my_main-41-42-"), "{name}");
+        assert_eq!(hash.len(), "01234567.png".len(), "{name}");
+    }
+
+    #[test]
+    fn existing_files_get_numbered() {
+        let dir = std::env::temp_dir().join(format!("snapshot-names-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config {
+            snapshot_dir: Some(dir.to_string_lossy().into_owned()),
+            filename_template: "{file}.{ext}".to_string(),
+            ..Config::default()
+        };
+
+        let first = resolve_output_path(&input(config.clone()));
+        std::fs::write(&first, b"").unwrap();
+        let second = resolve_output_path(&input(config.clone()));
+        std::fs::write(&second, b"").unwrap();
+        let third = resolve_output_path(&input(config));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(first.ends_with("/my_main.png"), "{first}");
+        assert!(second.ends_with("/my_main-1.png"), "{second}");
+        assert!(third.ends_with("/my_main-2.png"), "{third}");
+    }
+
+    #[test]
+    fn overwrite_policy_guards_existing_files() {
+        let dir = std::env::temp_dir().join(format!("snapshot-overwrite-{}", std::process::id()));
+        let path = dir.join("out.png").to_string_lossy().into_owned();
+        let save = |overwrite, bytes: &'static [u8]| {
+            let config = Config {
+                overwrite,
+                ..Config::default()
+            };
+            save_with(&path, &input(config), |_, writer| {
+                Ok(writer.write_all(bytes)?)
+            })
+        };
+
+        let first = save(Overwrite::Never, b"first").unwrap();
+        let blocked = save(Overwrite::Never, b"second").unwrap_err().to_string();
+        let renamed = save(Overwrite::Rename, b"third").unwrap();
+        save(Overwrite::Always, b"fourth").unwrap();
+        let contents = |path: &str| std::fs::read(path).unwrap();
+        let (first_bytes, renamed_bytes) = (contents(&first), contents(&renamed));
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(blocked.contains("out.png already exists"), "{blocked}");
+        assert!(renamed.ends_with("/out-1.png"), "{renamed}");
+        assert_eq!(first_bytes, b"fourth");
+        assert_eq!(renamed_bytes, b"third");
+        assert_eq!(files, 2, "temporary files are renamed into place");
+    }
+
+    #[test]
+    fn publishing_never_replaces_a_file_created_meanwhile() {
+        let dir = std::env::temp_dir().join(format!("snapshot-publish-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.png").to_string_lossy().into_owned();
+        let temp = dir.join(".out.png.tmp");
+        // The file appears after the output path was chosen, as a concurrent save would.
+        std::fs::write(&path, b"theirs").unwrap();
+
+        std::fs::write(&temp, b"ours").unwrap();
+        let blocked = publish(&temp, &path, Overwrite::Never).unwrap_err();
+        let renamed = publish(&temp, &path, Overwrite::Rename).unwrap();
+        let (theirs, ours) = (
+            std::fs::read(&path).unwrap(),
+            std::fs::read(&renamed).unwrap(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(blocked.to_string().contains("already exists"), "{blocked}");
+        assert!(renamed.ends_with("/out-1.png"), "{renamed}");
+        assert_eq!(
+            (theirs.as_slice(), ours.as_slice()),
+            (&b"theirs"[..], &b"ours"[..])
+        );
+    }
+
+    #[test]
+    fn publishing_copies_where_hard_links_are_unsupported() {
+        let dir = std::env::temp_dir().join(format!("snapshot-copy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.png").to_string_lossy().into_owned();
+        let temp = dir.join(".out.png.tmp");
+        std::fs::write(&temp, b"ours").unwrap();
+        let no_links = |_: &_, _: &_| Err(std::io::ErrorKind::Unsupported.into());
+
+        let saved = publish_with(&temp, &path, Overwrite::Never, no_links).unwrap();
+        let blocked = publish_with(&temp, &path, Overwrite::Never, no_links).unwrap_err();
+        let renamed = publish_with(&temp, &path, Overwrite::Rename, no_links).unwrap();
+        let (first, second) = (
+            std::fs::read(&saved).unwrap(),
+            std::fs::read(&renamed).unwrap(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(saved, path);
+        assert!(blocked.to_string().contains("already exists"), "{blocked}");
+        assert!(renamed.ends_with("/out-1.png"), "{renamed}");
+        assert_eq!(
+            (first.as_slice(), second.as_slice()),
+            (&b"ours"[..], &b"ours"[..])
+        );
+    }
+
+    #[test]
+    fn text_output_is_base64_or_a_data_uri() {
+        let bytes = b"GIF89a";
+        assert_eq!(
+            encode_text(bytes, OutputFormat::Gif, OutputEncoding::Base64),
+            "R0lGODlh"
+        );
+        assert_eq!(
+            encode_text(bytes, OutputFormat::Gif, OutputEncoding::DataUri),
+            "data:image/gif;base64,R0lGODlh"
+        );
+    }
+}
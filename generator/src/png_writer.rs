@@ -0,0 +1,363 @@
+//! Size-optimized PNG writer.
+//!
+//! Snapshots without a shadow usually use only a handful of colors, so they
+//! are written as indexed PNGs with the smallest bit depth that fits. Opaque
+//! images drop their alpha channel.
+
+use anyhow::{Context, Result};
+use image::{Rgba, RgbaImage};
+use png::text_metadata::ITXtChunk;
+use png::{BitDepth, ColorType, DeflateCompression, Filter, PixelDimensions, Unit};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::animation::Frames;
+use crate::input::PngCompression;
+use crate::metadata::{dpi, text_chunks, TextChunk};
+use crate::Input;
+
+/// Pixel data in the most compact color type that represents it exactly.
+struct Reduced {
+    width: u32,
+    height: u32,
+    color: ColorType,
+    depth: BitDepth,
+    data: Vec<u8>,
+    palette: Vec<u8>,
+    trns: Vec<u8>,
+}
+
+/// Palette of at most 256 colors, ordered so translucent entries come first
+/// and the tRNS chunk can stop at the last one.
+fn build_palette(image: &RgbaImage) -> Option<Vec<Rgba<u8>>> {
+    let mut colors = Vec::new();
+    let mut seen = HashSet::new();
+    for &pixel in image.pixels() {
+        if seen.insert(pixel) {
+            if colors.len() == 256 {
+                return None;
+            }
+            colors.push(pixel);
+        }
+    }
+    colors.sort_by_key(|color| color[3] == 255);
+    Some(colors)
+}
+
+fn bit_depth(colors: usize) -> (BitDepth, usize) {
+    match colors {
+        0..=2 => (BitDepth::One, 1),
+        3..=4 => (BitDepth::Two, 2),
+        5..=16 => (BitDepth::Four, 4),
+        _ => (BitDepth::Eight, 8),
+    }
+}
+
+fn indexed(image: &RgbaImage, colors: &[Rgba<u8>]) -> Reduced {
+    let index: HashMap<_, _> = colors
+        .iter()
+        .enumerate()
+        .map(|(idx, &color)| (color, idx as u8))
+        .collect();
+    let (depth, bits) = bit_depth(colors.len());
+    let per_byte = 8 / bits;
+    let row_bytes = (image.width() as usize).div_ceil(per_byte);
+
+    let mut data = vec![0u8; row_bytes * image.height() as usize];
+    for (row, out) in image.rows().zip(data.chunks_exact_mut(row_bytes)) {
+        for (x, pixel) in row.enumerate() {
+            let shift = 8 - bits * (x % per_byte + 1);
+            out[x / per_byte] |= index[pixel] << shift;
+        }
+    }
+
+    Reduced {
+        width: image.width(),
+        height: image.height(),
+        color: ColorType::Indexed,
+        depth,
+        data,
+        palette: colors
+            .iter()
+            .flat_map(|color| [color[0], color[1], color[2]])
+            .collect(),
+        trns: colors
+            .iter()
+            .take_while(|color| color[3] < 255)
+            .map(|color| color[3])
+            .collect(),
+    }
+}
+
+fn rgb_bytes(image: &RgbaImage) -> Vec<u8> {
+    image
+        .pixels()
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect()
+}
+
+fn reduce(image: &RgbaImage) -> Reduced {
+    if let Some(colors) = build_palette(image) {
+        return indexed(image, &colors);
+    }
+
+    let (color, data) = if image.pixels().all(|pixel| pixel[3] == 255) {
+        (ColorType::Rgb, rgb_bytes(image))
+    } else {
+        (ColorType::Rgba, image.as_raw().clone())
+    };
+
+    Reduced {
+        width: image.width(),
+        height: image.height(),
+        color,
+        depth: BitDepth::Eight,
+        data,
+        palette: Vec::new(),
+        trns: Vec::new(),
+    }
+}
+
+/// Ancillary chunks written before the image data.
+struct Ancillary {
+    pixel_dims: PixelDimensions,
+    text: Vec<ITXtChunk>,
+}
+
+fn pixel_dims(dpi: f32) -> PixelDimensions {
+    let per_meter = (dpi / 0.0254).round() as u32;
+    PixelDimensions {
+        xppu: per_meter,
+        yppu: per_meter,
+        unit: Unit::Meter,
+    }
+}
+
+fn itxt_chunks(chunks: Vec<TextChunk>) -> Result<Vec<ITXtChunk>> {
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            let mut itxt = ITXtChunk::new(chunk.keyword, chunk.text);
+            if chunk.compressed {
+                itxt.compress_text()?;
+            }
+            Ok(itxt)
+        })
+        .collect::<Result<_, png::EncodingError>>()
+        .context("Failed to encode png metadata")
+}
+
+fn encode(
+    reduced: &Reduced,
+    ancillary: &Ancillary,
+    deflate: DeflateCompression,
+    filter: Filter,
+) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, reduced.width, reduced.height);
+    encoder.set_color(reduced.color);
+    encoder.set_depth(reduced.depth);
+    if !reduced.palette.is_empty() {
+        encoder.set_palette(reduced.palette.as_slice());
+    }
+    if !reduced.trns.is_empty() {
+        encoder.set_trns(reduced.trns.as_slice());
+    }
+    encoder.set_pixel_dims(Some(ancillary.pixel_dims));
+    encoder.set_deflate_compression(deflate);
+    encoder.set_filter(filter);
+
+    let mut writer = encoder.write_header().context("Failed to encode png")?;
+    for chunk in &ancillary.text {
+        writer
+            .write_text_chunk(chunk)
+            .context("Failed to encode png metadata")?;
+    }
+    writer
+        .write_image_data(&reduced.data)
+        .context("Failed to encode png")?;
+    writer.finish().context("Failed to encode png")?;
+    Ok(bytes)
+}
+
+/// Write `image` as a PNG with the metadata and DPI from `input`, picking the
+/// smallest lossless color type. With [`PngCompression::Best`] several filter
+/// strategies are tried at maximum deflate level and the smallest result is
+/// kept.
+pub fn write_png(image: &RgbaImage, input: &Input, mut writer: impl Write) -> Result<()> {
+    let config = &input.config;
+    let ancillary = Ancillary {
+        pixel_dims: pixel_dims(dpi(config)),
+        text: itxt_chunks(text_chunks(input))?,
+    };
+    let reduced = reduce(image);
+
+    let bytes = match config.png_compression {
+        PngCompression::Fast => encode(
+            &reduced,
+            &ancillary,
+            DeflateCompression::FdeflateUltraFast,
+            Filter::Adaptive,
+        )?,
+        PngCompression::Default => encode(
+            &reduced,
+            &ancillary,
+            DeflateCompression::Level(6),
+            Filter::Adaptive,
+        )?,
+        PngCompression::Best => {
+            let mut filters = vec![Filter::Adaptive, Filter::MinEntropy];
+            if reduced.color == ColorType::Indexed {
+                filters.push(Filter::NoFilter);
+            }
+            filters
+                .into_par_iter()
+                .map(|filter| encode(&reduced, &ancillary, DeflateCompression::Level(9), filter))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .min_by_key(Vec::len)
+                .expect("at least one filter is tried")
+        }
+    };
+
+    writer.write_all(&bytes).context("Failed to encode png")
+}
+
+/// Write a looping animated PNG. Frames share one color type and one alpha
+/// channel, which comes from the backdrop and the rounded corners, so alpha is
+/// dropped when the first frame is opaque.
+pub fn write_apng(frames: &Frames, input: &Input, writer: impl Write) -> Result<()> {
+    let config = &input.config;
+    let text = itxt_chunks(text_chunks(input))?;
+    let mut images = frames.iter().peekable();
+    let opaque = images
+        .peek()
+        .is_some_and(|frame| frame.image.pixels().all(|pixel| pixel[3] == 255));
+    let (width, height) = frames.dimensions();
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(if opaque {
+        ColorType::Rgb
+    } else {
+        ColorType::Rgba
+    });
+    encoder.set_depth(BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .context("Failed to encode png")?;
+    encoder.set_pixel_dims(Some(pixel_dims(dpi(config))));
+    encoder.set_deflate_compression(match config.png_compression {
+        PngCompression::Fast => DeflateCompression::FdeflateUltraFast,
+        PngCompression::Default => DeflateCompression::Level(6),
+        PngCompression::Best => DeflateCompression::Level(9),
+    });
+    encoder.set_filter(Filter::Adaptive);
+
+    let mut writer = encoder.write_header().context("Failed to encode png")?;
+    for chunk in &text {
+        writer
+            .write_text_chunk(chunk)
+            .context("Failed to encode png metadata")?;
+    }
+    for frame in images {
+        let delay = frame.delay_ms.min(u16::MAX as u32) as u16;
+        writer
+            .set_frame_delay(delay, 1000)
+            .context("Failed to encode png")?;
+        let data = if opaque {
+            rgb_bytes(&frame.image)
+        } else {
+            frame.image.into_raw()
+        };
+        writer
+            .write_image_data(&data)
+            .context("Failed to encode png")?;
+    }
+    writer.finish().context("Failed to encode png")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_png;
+    use crate::input::Blending;
+    use crate::utils::apply_rounded_corners;
+    use crate::Input;
+    use image::{ColorType, ImageBuffer, Rgba, RgbaImage};
+    use serde_json::json;
+
+    fn encode(image: &RgbaImage, outer_background: &str) -> Vec<u8> {
+        let input: Input = serde_json::from_value(json!({
+            "lines": [],
+            "config": { "outer_background": outer_background }
+        }))
+        .unwrap();
+        let mut bytes = Vec::new();
+        write_png(image, &input, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn few_colors_round_trip_through_a_palette() {
+        let image = ImageBuffer::from_fn(13, 5, |x, y| match (x + y) % 3 {
+            0 => Rgba([255, 0, 0, 255]),
+            1 => Rgba([0, 0, 0, 0]),
+            _ => Rgba([0, 255, 0, 128]),
+        });
+        let bytes = encode(&image, "#00000000");
+
+        assert_eq!(bytes[25], 3, "color type should be indexed");
+        assert_eq!(bytes[24], 2, "three colors fit in two bits");
+        let decoded = image::load_from_memory(&bytes).unwrap().to_rgba8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn opaque_background_keeps_transparent_corners() {
+        let mut image = ImageBuffer::from_fn(40, 40, |x, y| {
+            Rgba([x as u8 * 6, y as u8 * 6, (x * y) as u8, 255])
+        });
+        apply_rounded_corners(&mut image, 8, Blending::Linear);
+        let bytes = encode(&image, "#ffffff");
+
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(decoded.color(), ColorType::Rgba8);
+        assert_eq!(decoded.to_rgba8().get_pixel(0, 0)[3], 0);
+        assert_eq!(decoded.to_rgba8(), image);
+    }
+
+    #[test]
+    fn opaque_images_drop_alpha() {
+        let image = ImageBuffer::from_fn(40, 40, |x, y| {
+            Rgba([x as u8 * 6, y as u8 * 6, (x * y) as u8, 255])
+        });
+        let bytes = encode(&image, "#00000000");
+
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(decoded.color(), ColorType::Rgb8);
+        assert_eq!(decoded.to_rgba8(), image);
+    }
+
+    #[test]
+    fn many_translucent_colors_keep_rgba() {
+        let image = ImageBuffer::from_fn(40, 40, |x, y| Rgba([x as u8, y as u8, 0, x as u8]));
+        let bytes = encode(&image, "#00000000");
+
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(decoded.color(), ColorType::Rgba8);
+        assert_eq!(decoded.to_rgba8(), image);
+    }
+
+    #[test]
+    fn physical_size_follows_scale() {
+        let image = ImageBuffer::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        let bytes = encode(&image, "#ffffff");
+
+        let reader = png::Decoder::new(std::io::Cursor::new(bytes))
+            .read_info()
+            .unwrap();
+        let dims = reader.info().pixel_dims.unwrap();
+        assert_eq!(dims.unit, png::Unit::Meter);
+        assert_eq!(dims.xppu, 5669, "144 dpi at the default scale of 2");
+    }
+}
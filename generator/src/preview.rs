@@ -0,0 +1,244 @@
+//! Inline image preview in the terminal, for checking a snapshot over SSH
+//! without opening a viewer. Supports the kitty graphics protocol, iTerm2
+//! inline images and Sixel.
+
+use anyhow::{anyhow, Context, Result};
+use color_quant::NeuQuant;
+use image::RgbaImage;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::encode::{fast_png, flatten};
+use crate::utils::{base64, hex_to_rgba};
+use crate::Config;
+
+/// Base64 bytes per kitty graphics escape sequence.
+const KITTY_CHUNK: usize = 4096;
+/// Palette size for Sixel output.
+const SIXEL_COLORS: usize = 256;
+/// NeuQuant sampling factor, as for GIF palettes.
+const SIXEL_SAMPLING: i32 = 10;
+
+/// Terminal image protocol used for the preview.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewProtocol {
+    Kitty,
+    Iterm,
+    Sixel,
+}
+
+impl FromStr for PreviewProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "kitty" => Ok(Self::Kitty),
+            "iterm" | "iterm2" => Ok(Self::Iterm),
+            "sixel" => Ok(Self::Sixel),
+            _ => Err(anyhow!(
+                "Unknown preview protocol `{name}`, expected kitty, iterm or sixel"
+            )),
+        }
+    }
+}
+
+/// Guess the protocol from the environment variables terminals set, which
+/// `var` looks up. `TERM` and `LC_TERMINAL` usually survive SSH.
+pub fn detect_protocol(var: impl Fn(&str) -> Option<String>) -> Option<PreviewProtocol> {
+    let term = var("TERM").unwrap_or_default();
+    let program = var("TERM_PROGRAM").unwrap_or_default();
+
+    if var("KITTY_WINDOW_ID").is_some()
+        || term == "xterm-kitty"
+        || term == "xterm-ghostty"
+        || matches!(program.as_str(), "WezTerm" | "ghostty")
+    {
+        Some(PreviewProtocol::Kitty)
+    } else if program == "iTerm.app" || var("LC_TERMINAL").as_deref() == Some("iTerm2") {
+        Some(PreviewProtocol::Iterm)
+    } else if ["foot", "mlterm", "sixel", "contour"]
+        .iter()
+        .any(|name| term.contains(name))
+    {
+        Some(PreviewProtocol::Sixel)
+    } else {
+        None
+    }
+}
+
+/// Protocol for this terminal, or an error suggesting how to force one.
+pub fn detect_terminal() -> Result<PreviewProtocol> {
+    detect_protocol(|name| std::env::var(name).ok()).ok_or_else(|| {
+        anyhow!(
+            "Could not detect an image-capable terminal, \
+             use --preview=kitty, --preview=iterm or --preview=sixel"
+        )
+    })
+}
+
+/// PNG data sent in chunks with the kitty graphics protocol.
+fn kitty(image: &RgbaImage) -> Result<String> {
+    let encoded = base64(&fast_png(image)?);
+    let chunks: Vec<_> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+
+    let mut out = String::new();
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(idx + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).expect("base64 is ascii");
+        if idx == 0 {
+            let _ = write!(out, "\x1b_Gf=100,a=T,m={more};{chunk}\x1b\\");
+        } else {
+            let _ = write!(out, "\x1b_Gm={more};{chunk}\x1b\\");
+        }
+    }
+    Ok(out)
+}
+
+/// A PNG file shown inline with iTerm2's OSC 1337 sequence.
+fn iterm(image: &RgbaImage) -> Result<String> {
+    let png = fast_png(image)?;
+    Ok(format!(
+        "\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07",
+        png.len(),
+        base64(&png)
+    ))
+}
+
+/// Append one Sixel run of `count` columns with `bits` set.
+fn push_run(out: &mut String, bits: u8, count: usize) {
+    let c = (63 + bits) as char;
+    match count {
+        0 => {}
+        1..=3 => (0..count).for_each(|_| out.push(c)),
+        _ => {
+            let _ = write!(out, "!{count}{c}");
+        }
+    }
+}
+
+/// Sixel image with a quantized palette. Sixel has no alpha, so the image is
+/// flattened onto `outer_background` first.
+fn sixel(image: &RgbaImage, config: &Config) -> String {
+    let image = flatten(
+        image,
+        hex_to_rgba(&config.outer_background),
+        config.blending,
+    );
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let quant = NeuQuant::new(SIXEL_SAMPLING, SIXEL_COLORS, image.as_raw());
+    let indices: Vec<usize> = image
+        .pixels()
+        .map(|pixel| quant.index_of(&pixel.0))
+        .collect();
+
+    let mut out = format!("\x1bPq\"1;1;{width};{height}");
+    for (idx, rgb) in quant.color_map_rgb().chunks_exact(3).enumerate() {
+        let percent = |value: u8| value as u32 * 100 / 255;
+        let (r, g, b) = (percent(rgb[0]), percent(rgb[1]), percent(rgb[2]));
+        let _ = write!(out, "#{idx};2;{r};{g};{b}");
+    }
+
+    let mut bands = vec![0u8; SIXEL_COLORS * width];
+    for top in (0..height).step_by(6) {
+        bands.fill(0);
+        let mut used = [false; SIXEL_COLORS];
+        for row in top..(top + 6).min(height) {
+            for x in 0..width {
+                let color = indices[row * width + x];
+                bands[color * width + x] |= 1 << (row - top);
+                used[color] = true;
+            }
+        }
+
+        let mut first = true;
+        for color in (0..SIXEL_COLORS).filter(|&color| used[color]) {
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            let _ = write!(out, "#{color}");
+
+            let columns = &bands[color * width..(color + 1) * width];
+            let mut run = (columns[0], 0);
+            for &bits in columns {
+                if bits == run.0 {
+                    run.1 += 1;
+                } else {
+                    push_run(&mut out, run.0, run.1);
+                    run = (bits, 1);
+                }
+            }
+            push_run(&mut out, run.0, run.1);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Draw `image` in the terminal behind `writer`, followed by a newline.
+pub fn write_preview(
+    image: &RgbaImage,
+    protocol: PreviewProtocol,
+    config: &Config,
+    mut writer: impl Write,
+) -> Result<()> {
+    let escapes = match protocol {
+        PreviewProtocol::Kitty => kitty(image)?,
+        PreviewProtocol::Iterm => iterm(image)?,
+        PreviewProtocol::Sixel => sixel(image, config),
+    };
+    writeln!(writer, "{escapes}")
+        .and_then(|()| writer.flush())
+        .context("Failed to write preview")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_protocol, sixel, PreviewProtocol};
+    use crate::Config;
+    use image::{ImageBuffer, Rgba};
+
+    fn detect(vars: &[(&str, &str)]) -> Option<PreviewProtocol> {
+        detect_protocol(|name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn protocol_comes_from_the_environment() {
+        assert_eq!(
+            detect(&[("TERM", "xterm-kitty")]),
+            Some(PreviewProtocol::Kitty)
+        );
+        assert_eq!(
+            detect(&[("TERM", "xterm-256color"), ("LC_TERMINAL", "iTerm2")]),
+            Some(PreviewProtocol::Iterm)
+        );
+        assert_eq!(detect(&[("TERM", "foot")]), Some(PreviewProtocol::Sixel));
+        assert_eq!(detect(&[("TERM", "xterm-256color")]), None);
+    }
+
+    #[test]
+    fn sixel_encodes_bands_with_runs() {
+        let image = ImageBuffer::from_fn(10, 7, |x, _| {
+            if x < 5 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let out = sixel(&image, &Config::default());
+
+        assert!(out.starts_with("\x1bPq\"1;1;10;7#0;2;"));
+        assert!(out.ends_with("\x1b\\"));
+        assert_eq!(out.matches('-').count(), 2, "7 rows make two bands");
+        assert!(
+            out.contains("!5~!5?"),
+            "a full first band is one run per color"
+        );
+    }
+}
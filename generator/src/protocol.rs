@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+/// Version of the JSON contract between frontends and the generator.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Protocol versions this binary accepts. Inputs without a version are
+/// treated as version 1, which predates the field.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u32] = &[1];
+
+const OUTPUT_FORMATS: &[&str] = &["png", "jpeg", "webp", "avif", "gif", "html"];
+
+const FEATURES: &[&str] = &[
+    "clipboard",
+    "shadow",
+    "line_numbers",
+    "validation",
+    "batch",
+    "linear_blending",
+    "encoder_options",
+    "png_optimization",
+    "source_metadata",
+    "dpi_metadata",
+    "animation",
+    "transition",
+    "html",
+    "stdout_output",
+    "filename_template",
+    "overwrite",
+    "clipboard_backends",
+    "clipboard_only",
+    #[cfg(feature = "webp-lossy")]
+    "lossy_webp",
+];
+
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    pub version: &'static str,
+    pub protocol_version: u32,
+    pub protocol_versions: &'static [u32],
+    pub output_formats: &'static [&'static str],
+    pub features: &'static [&'static str],
+}
+
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        protocol_version: PROTOCOL_VERSION,
+        protocol_versions: SUPPORTED_PROTOCOL_VERSIONS,
+        output_formats: OUTPUT_FORMATS,
+        features: FEATURES,
+    }
+}
+
+pub fn check_protocol_version(version: Option<u32>) -> Result<()> {
+    let version = version.unwrap_or(1);
+    if SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "Unsupported protocol version {version} (this generator supports {SUPPORTED_PROTOCOL_VERSIONS:?}). \
+         Please update the snapshot generator binary."
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capabilities, check_protocol_version, PROTOCOL_VERSION};
+
+    #[test]
+    fn accepts_current_and_missing_versions() {
+        assert!(check_protocol_version(None).is_ok());
+        assert!(check_protocol_version(Some(PROTOCOL_VERSION)).is_ok());
+        assert!(check_protocol_version(Some(PROTOCOL_VERSION + 1)).is_err());
+    }
+
+    #[test]
+    fn capabilities_list_current_protocol() {
+        let json = serde_json::to_value(capabilities()).unwrap();
+
+        assert_eq!(json["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(json["protocol_versions"][0], PROTOCOL_VERSION);
+        assert!(json["output_formats"]
+            .as_array()
+            .unwrap()
+            .contains(&"png".into()));
+    }
+}
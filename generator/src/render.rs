@@ -0,0 +1,445 @@
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use image::{ImageBuffer, Rgba, RgbaImage};
+use rayon::prelude::*;
+
+use crate::glyphs::{draw_text, text_advance, GlyphCache};
+use crate::shadow::{composite_image_onto, shadow_backdrop};
+use crate::utils::{apply_rounded_corners, hex_to_rgba};
+use crate::{Config, Input, Line};
+#[cfg(feature = "native")]
+use {crate::font::load_font, anyhow::Result};
+
+const FALLBACK_RENDER_SCALE: f32 = 2.0;
+pub(crate) const LINE_NUMBER_COLOR_HEX: &str = "#5c6370";
+/// Number of text lines rasterized together in one parallel band.
+const BAND_LINES: f32 = 4.0;
+
+pub(crate) struct RenderConfig {
+    pub(crate) render_scale: f32,
+    pub(crate) scale: PxScale,
+    pub(crate) scaled_padding: u32,
+    pub(crate) scaled_line_height: f32,
+    scaled_outer_padding: u32,
+    pub(crate) bg_color: Rgba<u8>,
+    pub(crate) default_fg: Rgba<u8>,
+    outer_bg: Rgba<u8>,
+    pub(crate) line_number_color: Rgba<u8>,
+}
+
+impl RenderConfig {
+    /// Top of line `line_idx` on the card, in whole pixels.
+    pub(crate) fn line_top(&self, line_idx: usize) -> i32 {
+        self.scaled_padding as i32 + (line_idx as f32 * self.scaled_line_height) as i32
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct PreparedSegment {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) width: f32,
+    pub(crate) color: Rgba<u8>,
+}
+
+#[derive(Clone)]
+pub(crate) struct PreparedLine {
+    pub(crate) width: f32,
+    pub(crate) segments: Vec<PreparedSegment>,
+}
+
+pub(crate) fn render_scale(scale: f32) -> f32 {
+    if scale > 0.0 {
+        scale
+    } else {
+        FALLBACK_RENDER_SCALE
+    }
+}
+
+pub(crate) fn build_render_config(config: &Config) -> RenderConfig {
+    let render_scale = render_scale(config.scale);
+    let scaled_font_size = config.font_size * render_scale;
+
+    RenderConfig {
+        render_scale,
+        scale: PxScale::from(scaled_font_size),
+        scaled_padding: (config.padding as f32 * render_scale) as u32,
+        scaled_line_height: config.line_height * render_scale,
+        scaled_outer_padding: (config.outer_padding as f32 * render_scale) as u32,
+        bg_color: hex_to_rgba(&config.background),
+        default_fg: hex_to_rgba(&config.foreground),
+        outer_bg: hex_to_rgba(&config.outer_background),
+        line_number_color: hex_to_rgba(LINE_NUMBER_COLOR_HEX),
+    }
+}
+
+fn normalize_index(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while index > 0 && !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+pub(crate) fn normalize_span_range(
+    text: &str,
+    start: usize,
+    end: usize,
+    last_end: usize,
+) -> Option<(usize, usize)> {
+    let start = normalize_index(text, start.max(last_end));
+    let end = normalize_index(text, end);
+    if start < end {
+        Some((start, end))
+    } else {
+        None
+    }
+}
+
+fn push_segment<F, SF>(
+    segments: &mut Vec<PreparedSegment>,
+    text: &str,
+    start: usize,
+    end: usize,
+    color: Rgba<u8>,
+    scaled_font: &SF,
+) where
+    F: Font,
+    SF: ab_glyph::ScaleFont<F>,
+{
+    if start >= end {
+        return;
+    }
+
+    let width = text_advance(&text[start..end], scaled_font);
+    segments.push(PreparedSegment {
+        start,
+        end,
+        width,
+        color,
+    });
+}
+
+fn prepare_line<F, SF>(line: &Line, scaled_font: &SF, default_fg: Rgba<u8>) -> PreparedLine
+where
+    F: Font,
+    SF: ab_glyph::ScaleFont<F>,
+{
+    let mut segments = Vec::with_capacity(line.spans.len().saturating_mul(2).max(1));
+    let mut last_end = 0;
+
+    for span in &line.spans {
+        let Some((start, end)) = normalize_span_range(&line.text, span.start, span.end, last_end)
+        else {
+            continue;
+        };
+
+        if last_end < start {
+            push_segment(
+                &mut segments,
+                &line.text,
+                last_end,
+                start,
+                default_fg,
+                scaled_font,
+            );
+        }
+
+        let color = span.fg.as_deref().map(hex_to_rgba).unwrap_or(default_fg);
+        push_segment(&mut segments, &line.text, start, end, color, scaled_font);
+        last_end = end;
+    }
+
+    if last_end < line.text.len() {
+        push_segment(
+            &mut segments,
+            &line.text,
+            last_end,
+            line.text.len(),
+            default_fg,
+            scaled_font,
+        );
+    }
+
+    let width = segments.iter().map(|segment| segment.width).sum();
+    PreparedLine { width, segments }
+}
+
+fn line_number_width<F, SF>(config: &Config, lines: &[Line], scaled_font: &SF) -> f32
+where
+    F: Font,
+    SF: ab_glyph::ScaleFont<F>,
+{
+    if !config.line_numbers {
+        return 0.0;
+    }
+
+    let max_line_num = config.start_line + lines.len();
+    let line_num = format!("{max_line_num:>4}  ");
+    text_advance(&line_num, scaled_font)
+}
+
+pub(crate) fn measure_layout<F, SF>(
+    lines: &[Line],
+    config: &Config,
+    render: &RenderConfig,
+    scaled_font: &SF,
+) -> (Vec<PreparedLine>, f32, u32, u32)
+where
+    F: Font,
+    SF: ab_glyph::ScaleFont<F>,
+{
+    let prepared_lines: Vec<_> = lines
+        .iter()
+        .map(|line| prepare_line(line, scaled_font, render.default_fg))
+        .collect();
+
+    let line_number_width = line_number_width(config, lines, scaled_font);
+    let max_content_width = prepared_lines
+        .iter()
+        .map(|line| line.width)
+        .reduce(f32::max)
+        .unwrap_or(800.0 * render.render_scale);
+
+    let width = (max_content_width + line_number_width).ceil() as u32 + render.scaled_padding * 2;
+    let height =
+        (lines.len() as f32 * render.scaled_line_height) as u32 + render.scaled_padding * 2;
+
+    (prepared_lines, line_number_width, width, height)
+}
+
+type Band<'a> = ImageBuffer<Rgba<u8>, &'a mut [u8]>;
+
+#[allow(clippy::too_many_arguments)]
+fn draw_line(
+    band: &mut Band,
+    line: &Line,
+    prepared: &PreparedLine,
+    line_idx: usize,
+    y: i32,
+    config: &Config,
+    render: &RenderConfig,
+    font: &FontVec,
+    glyphs: &GlyphCache,
+    line_number_width: f32,
+) {
+    let mut x = render.scaled_padding as f32;
+
+    if config.line_numbers {
+        let line_num = format!("{:>4}  ", config.start_line + line_idx);
+        draw_text(
+            band,
+            glyphs,
+            font,
+            render.scale,
+            render.line_number_color,
+            config.blending,
+            x,
+            y,
+            &line_num,
+        );
+        x += line_number_width;
+    }
+
+    for segment in &prepared.segments {
+        let text = &line.text[segment.start..segment.end];
+        x = draw_text(
+            band,
+            glyphs,
+            font,
+            render.scale,
+            segment.color,
+            config.blending,
+            x,
+            y,
+            text,
+        );
+    }
+}
+
+/// Rasterize lines in parallel horizontal bands. Each band draws every line
+/// whose glyphs can reach it, clipped to the band, so the result is identical
+/// to drawing the lines top to bottom.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render_card(
+    lines: &[Line],
+    prepared_lines: &[PreparedLine],
+    config: &Config,
+    render: &RenderConfig,
+    font: &FontVec,
+    glyphs: &GlyphCache,
+    line_number_width: f32,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let mut image: RgbaImage = ImageBuffer::from_pixel(width, height, render.bg_color);
+    if width == 0 || height == 0 {
+        return image;
+    }
+
+    let row_bytes = width as usize * 4;
+    let band_rows = ((render.scaled_line_height * BAND_LINES).ceil() as usize).max(1);
+    let glyph_extent = font.as_scaled(render.scale).height().ceil() as i32;
+
+    image
+        .par_chunks_mut(row_bytes * band_rows)
+        .enumerate()
+        .for_each(|(band_idx, chunk)| {
+            let band_top = (band_idx * band_rows) as i32;
+            let rows = chunk.len() / row_bytes;
+            let band_bottom = band_top + rows as i32;
+            let mut band =
+                Band::from_raw(width, rows as u32, chunk).expect("band spans whole image rows");
+
+            for (line_idx, (line, prepared)) in lines.iter().zip(prepared_lines).enumerate() {
+                let y = render.line_top(line_idx);
+                if y + 2 * glyph_extent < band_top || y - glyph_extent > band_bottom {
+                    continue;
+                }
+
+                draw_line(
+                    &mut band,
+                    line,
+                    prepared,
+                    line_idx,
+                    y - band_top,
+                    config,
+                    render,
+                    font,
+                    glyphs,
+                    line_number_width,
+                );
+            }
+        });
+
+    image
+}
+
+pub(crate) fn apply_card_effects(
+    mut image: RgbaImage,
+    config: &Config,
+    render: &RenderConfig,
+) -> RgbaImage {
+    if config.border_radius > 0 {
+        let scaled_radius = (config.border_radius as f32 * render.render_scale) as u32;
+        apply_rounded_corners(&mut image, scaled_radius, config.blending);
+    }
+    image
+}
+
+/// Outer background and shadow for `card`, and the margin the card is placed
+/// at. Only the card's size and alpha matter, so animation frames share one.
+pub(crate) fn backdrop(
+    card: &RgbaImage,
+    config: &Config,
+    render: &RenderConfig,
+) -> (RgbaImage, u32) {
+    if config.shadow {
+        let shadow_sigma = 20.0 * render.render_scale;
+        let shadow_opacity = 0.5;
+        let offset_x = 0;
+        let offset_y = (8.0 * render.render_scale) as i32;
+        shadow_backdrop(
+            card,
+            shadow_sigma,
+            shadow_opacity,
+            offset_x,
+            offset_y,
+            render.outer_bg,
+            render.scaled_outer_padding,
+            config.blending,
+        )
+    } else {
+        let margin = render.scaled_outer_padding;
+        let (cw, ch) = card.dimensions();
+        let out_w = cw + margin * 2;
+        let out_h = ch + margin * 2;
+        let output: RgbaImage = ImageBuffer::from_pixel(out_w, out_h, render.outer_bg);
+        (output, margin)
+    }
+}
+
+/// Composite `card` onto its backdrop and round the outer corners.
+pub(crate) fn place_card(
+    mut image: RgbaImage,
+    card: &RgbaImage,
+    margin: u32,
+    config: &Config,
+    render: &RenderConfig,
+) -> RgbaImage {
+    composite_image_onto(&mut image, card, margin, margin, config.blending);
+
+    if config.border_radius > 0 {
+        let scaled_radius = (config.border_radius as f32 * render.render_scale) as u32;
+        apply_rounded_corners(&mut image, scaled_radius, config.blending);
+    }
+
+    image
+}
+
+fn finalize_output(card: &RgbaImage, config: &Config, render: &RenderConfig) -> RgbaImage {
+    let (backdrop, margin) = backdrop(card, config, render);
+    place_card(backdrop, card, margin, config, render)
+}
+
+/// Render `input` into the final image, including the shadow and outer padding.
+pub fn render_with_font(input: &Input, font: &FontVec) -> RgbaImage {
+    render_with_cache(input, font, &GlyphCache::new())
+}
+
+/// Like [`render_with_font`], reusing glyphs rasterized by earlier renders.
+/// `glyphs` must only ever be used with `font`.
+pub fn render_with_cache(input: &Input, font: &FontVec, glyphs: &GlyphCache) -> RgbaImage {
+    let render = build_render_config(&input.config);
+    let scaled_font = font.as_scaled(render.scale);
+
+    let (prepared_lines, line_number_width, width, height) =
+        measure_layout(&input.lines, &input.config, &render, &scaled_font);
+    let card = render_card(
+        &input.lines,
+        &prepared_lines,
+        &input.config,
+        &render,
+        font,
+        glyphs,
+        line_number_width,
+        width,
+        height,
+    );
+    let card = apply_card_effects(card, &input.config, &render);
+    finalize_output(&card, &input.config, &render)
+}
+
+/// Render `input` with the bundled font. Use [`render_with_font`] to render
+/// several inputs without reloading the font.
+#[cfg(feature = "native")]
+pub fn render(input: &Input) -> Result<RgbaImage> {
+    let font = load_font()?;
+    Ok(render_with_font(input, &font))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_index, normalize_span_range, render_scale};
+
+    #[test]
+    fn invalid_scale_uses_fallback() {
+        assert_eq!(render_scale(0.0), 2.0);
+        assert_eq!(render_scale(-1.0), 2.0);
+        assert_eq!(render_scale(1.5), 1.5);
+    }
+
+    #[test]
+    fn normalize_index_moves_to_char_boundary() {
+        let text = "aé";
+        assert_eq!(normalize_index(text, 2), 1);
+        assert_eq!(normalize_index(text, 3), 3);
+    }
+
+    #[test]
+    fn normalize_span_range_skips_invalid_or_overlapping_ranges() {
+        let text = "hello";
+        assert_eq!(normalize_span_range(text, 1, 4, 0), Some((1, 4)));
+        assert_eq!(normalize_span_range(text, 0, 1, 2), None);
+        assert_eq!(normalize_span_range(text, 8, 10, 0), None);
+    }
+}
@@ -0,0 +1,70 @@
+use schemars::{schema_for, Schema};
+
+use crate::Input;
+
+/// JSON Schema describing the generator input, including field defaults.
+pub fn input_schema() -> Schema {
+    schema_for!(Input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::input_schema;
+    use crate::validate::{CONFIG_KEYS, INPUT_KEYS};
+    use crate::Config;
+    use serde_json::{json, Value};
+
+    fn property_names(properties: &Value) -> Vec<&str> {
+        let mut names: Vec<_> = properties
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    fn sorted(keys: &[&'static str]) -> Vec<&'static str> {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn schema_lists_every_known_key() {
+        let schema = input_schema().to_value();
+
+        assert_eq!(property_names(&schema["properties"]), sorted(INPUT_KEYS));
+        assert_eq!(
+            property_names(&schema["$defs"]["Config"]["properties"]),
+            sorted(CONFIG_KEYS)
+        );
+    }
+
+    #[test]
+    fn schema_defaults_match_serde_defaults() {
+        let schema = input_schema().to_value();
+        let defaults =
+            serde_json::to_value(serde_json::from_value::<Config>(json!({})).unwrap()).unwrap();
+        let properties = schema["$defs"]["Config"]["properties"].as_object().unwrap();
+
+        for (key, value) in defaults.as_object().unwrap() {
+            let documented = properties[key].get("default").unwrap_or(&Value::Null);
+            assert_eq!(documented, value, "default for `{key}`");
+        }
+    }
+
+    #[test]
+    fn config_fields_are_described() {
+        let schema = input_schema().to_value();
+        let properties = schema["$defs"]["Config"]["properties"].as_object().unwrap();
+
+        for (key, property) in properties {
+            assert!(
+                property.get("description").is_some(),
+                "`{key}` has no description"
+            );
+        }
+    }
+}
@@ -1,40 +1,62 @@
 use image::{ImageBuffer, Rgba, RgbaImage};
+use rayon::prelude::*;
+
+use crate::blend::source_over;
+use crate::input::Blending;
+
+/// Rows handled by one parallel task in the vertical blur.
+const BLUR_BAND_ROWS: usize = 64;
 
 /// Single-pass horizontal box blur on an alpha-only buffer stored row-major.
-fn box_blur_h(src: &[u8], dst: &mut [u8], w: usize, h: usize, radius: usize) {
+fn box_blur_h(src: &[u8], dst: &mut [u8], w: usize, radius: usize) {
     let diam = radius * 2 + 1;
-    for y in 0..h {
-        let row = y * w;
-        let mut sum: u32 = (radius as u32 + 1) * src[row] as u32;
-        for i in 0..radius {
-            sum += src[row + i.min(w - 1)] as u32;
-        }
-        for x in 0..w {
-            let right = (x + radius).min(w - 1);
-            let left = (x as isize - radius as isize - 1).max(0) as usize;
-            sum += src[row + right] as u32;
-            dst[row + x] = (sum / diam as u32).min(255) as u8;
-            sum -= src[row + left] as u32;
-        }
-    }
+    dst.par_chunks_mut(w)
+        .zip(src.par_chunks(w))
+        .for_each(|(dst, src)| {
+            let mut sum: u32 = (radius as u32 + 1) * src[0] as u32;
+            for i in 0..radius {
+                sum += src[i.min(w - 1)] as u32;
+            }
+            for (x, out) in dst.iter_mut().enumerate() {
+                let right = (x + radius).min(w - 1);
+                let left = (x as isize - radius as isize - 1).max(0) as usize;
+                sum += src[right] as u32;
+                *out = (sum / diam as u32).min(255) as u8;
+                sum -= src[left] as u32;
+            }
+        });
 }
 
 /// Single-pass vertical box blur on an alpha-only buffer stored row-major.
+/// Bands of rows are blurred in parallel, each seeding its running column sums
+/// with the window above its first row.
 fn box_blur_v(src: &[u8], dst: &mut [u8], w: usize, h: usize, radius: usize) {
     let diam = radius * 2 + 1;
-    for x in 0..w {
-        let mut sum: u32 = (radius as u32 + 1) * src[x] as u32;
-        for i in 0..radius {
-            sum += src[i.min(h - 1) * w + x] as u32;
-        }
-        for y in 0..h {
-            let bottom = (y + radius).min(h - 1);
-            let top = (y as isize - radius as isize - 1).max(0) as usize;
-            sum += src[bottom * w + x] as u32;
-            dst[y * w + x] = (sum / diam as u32).min(255) as u8;
-            sum -= src[top * w + x] as u32;
-        }
-    }
+    let clamp_row = |y: isize| y.clamp(0, h as isize - 1) as usize * w;
+
+    dst.par_chunks_mut(w * BLUR_BAND_ROWS)
+        .enumerate()
+        .for_each(|(band_idx, band)| {
+            let y0 = (band_idx * BLUR_BAND_ROWS) as isize;
+            let mut sums = vec![0u32; w];
+            for y in y0 - radius as isize - 1..y0 + radius as isize {
+                let row = &src[clamp_row(y)..][..w];
+                for (sum, &alpha) in sums.iter_mut().zip(row) {
+                    *sum += alpha as u32;
+                }
+            }
+
+            for (offset, dst_row) in band.chunks_mut(w).enumerate() {
+                let y = y0 + offset as isize;
+                let bottom = &src[clamp_row(y + radius as isize)..][..w];
+                let top = &src[clamp_row(y - radius as isize - 1)..][..w];
+                for x in 0..w {
+                    sums[x] += bottom[x] as u32;
+                    dst_row[x] = (sums[x] / diam as u32).min(255) as u8;
+                    sums[x] -= top[x] as u32;
+                }
+            }
+        });
 }
 
 /// Approximate Gaussian blur via three successive box-blur passes on an alpha buffer.
@@ -44,7 +66,7 @@ fn gaussian_blur_alpha(buf: &mut [u8], tmp: &mut [u8], w: usize, h: usize, sigma
         if radius == 0 {
             continue;
         }
-        box_blur_h(buf, tmp, w, h, radius);
+        box_blur_h(buf, tmp, w, radius);
         box_blur_v(tmp, buf, w, h, radius);
     }
 }
@@ -70,52 +92,53 @@ fn boxes_for_gauss(sigma: f32) -> [i32; 3] {
     sizes
 }
 
-fn source_over(src: Rgba<u8>, dst: Rgba<u8>) -> Rgba<u8> {
-    let sa = src[3] as f32 / 255.0;
-    if sa <= 0.0 {
-        return dst;
-    }
-
-    let da = dst[3] as f32 / 255.0;
-    let out_a = sa + da * (1.0 - sa);
-    if out_a <= 0.0 {
-        return Rgba([0, 0, 0, 0]);
-    }
-
-    let r = (src[0] as f32 * sa + dst[0] as f32 * da * (1.0 - sa)) / out_a;
-    let g = (src[1] as f32 * sa + dst[1] as f32 * da * (1.0 - sa)) / out_a;
-    let b = (src[2] as f32 * sa + dst[2] as f32 * da * (1.0 - sa)) / out_a;
-    Rgba([r as u8, g as u8, b as u8, (out_a * 255.0) as u8])
-}
-
+/// Alpha-blend `overlay` onto `output` with its top-left corner at the given offset.
 pub fn composite_image_onto(
     output: &mut RgbaImage,
     overlay: &RgbaImage,
     offset_x: u32,
     offset_y: u32,
+    blending: Blending,
 ) {
     let (ow, oh) = overlay.dimensions();
-    for y in 0..oh {
-        for x in 0..ow {
-            let src = *overlay.get_pixel(x, y);
-            if src[3] == 0 {
-                continue;
+    if ow == 0 || oh == 0 {
+        return;
+    }
+
+    let output_row = output.width() as usize * 4;
+    let overlay_row = ow as usize * 4;
+    let x_start = offset_x as usize * 4;
+
+    output
+        .par_chunks_mut(output_row)
+        .skip(offset_y as usize)
+        .zip(overlay.par_chunks(overlay_row))
+        .for_each(|(dst_row, src_row)| {
+            let dst_row = &mut dst_row[x_start..x_start + overlay_row];
+            for (dst, src) in dst_row.chunks_exact_mut(4).zip(src_row.chunks_exact(4)) {
+                if src[3] == 0 {
+                    continue;
+                }
+
+                let blended =
+                    source_over(Rgba([src[0], src[1], src[2], src[3]]), pixel(dst), blending);
+                dst.copy_from_slice(&blended.0);
             }
+        });
+}
 
-            let dx = x + offset_x;
-            let dy = y + offset_y;
-            let dst = *output.get_pixel(dx, dy);
-            output.put_pixel(dx, dy, source_over(src, dst));
-        }
-    }
+fn pixel(bytes: &[u8]) -> Rgba<u8> {
+    Rgba([bytes[0], bytes[1], bytes[2], bytes[3]])
 }
 
 fn has_visible_alpha(buf: &[u8]) -> bool {
     buf.iter().any(|&alpha| alpha > 0)
 }
 
-/// Compose a drop-shadow behind `card` and return the final image.
-pub fn apply_outer_shadow(
+/// Outer background with the drop shadow `card` casts, and the margin at which
+/// the card itself belongs.
+#[allow(clippy::too_many_arguments)]
+pub fn shadow_backdrop(
     card: &RgbaImage,
     shadow_blur: f32,
     shadow_opacity: f32,
@@ -123,7 +146,8 @@ pub fn apply_outer_shadow(
     offset_y: i32,
     outer_bg: Rgba<u8>,
     outer_padding: u32,
-) -> RgbaImage {
+    blending: Blending,
+) -> (RgbaImage, u32) {
     let (cw, ch) = card.dimensions();
     let blur_margin = if shadow_blur > 0.0 {
         (shadow_blur * 3.0).ceil() as u32
@@ -141,21 +165,24 @@ pub fn apply_outer_shadow(
         let buf_h = out_h as usize;
         let mut alpha_buf = vec![0u8; buf_w * buf_h];
 
-        for cy in 0..ch {
-            for cx in 0..cw {
-                let dx = margin as i32 + offset_x + cx as i32;
-                let dy = margin as i32 + offset_y + cy as i32;
-                if dx < 0 || dy < 0 {
-                    continue;
+        let card_row = cw as usize * 4;
+        alpha_buf
+            .par_chunks_mut(buf_w)
+            .enumerate()
+            .for_each(|(dy, row)| {
+                let cy = dy as i64 - margin as i64 - offset_y as i64;
+                if cy < 0 || cy >= ch as i64 {
+                    return;
                 }
 
-                let dx = dx as usize;
-                let dy = dy as usize;
-                if dx < buf_w && dy < buf_h {
-                    alpha_buf[dy * buf_w + dx] = card.get_pixel(cx, cy)[3];
+                let card_pixels = &card.as_raw()[cy as usize * card_row..][..card_row];
+                for (cx, card_pixel) in card_pixels.chunks_exact(4).enumerate() {
+                    let dx = margin as i64 + offset_x as i64 + cx as i64;
+                    if dx >= 0 && (dx as usize) < buf_w {
+                        row[dx as usize] = card_pixel[3];
+                    }
                 }
-            }
-        }
+            });
 
         if has_visible_alpha(&alpha_buf) {
             if shadow_blur > 0.0 {
@@ -163,33 +190,63 @@ pub fn apply_outer_shadow(
                 gaussian_blur_alpha(&mut alpha_buf, &mut tmp, buf_w, buf_h, shadow_blur);
             }
 
-            for y in 0..out_h {
-                for x in 0..out_w {
-                    let alpha = alpha_buf[y as usize * buf_w + x as usize];
-                    if alpha == 0 {
-                        continue;
-                    }
+            output
+                .par_chunks_mut(buf_w * 4)
+                .zip(alpha_buf.par_chunks(buf_w))
+                .for_each(|(row, alphas)| {
+                    for (dst, &alpha) in row.chunks_exact_mut(4).zip(alphas) {
+                        if alpha == 0 {
+                            continue;
+                        }
 
-                    let shadow_alpha = ((alpha as f32 / 255.0) * shadow_opacity * 255.0) as u8;
-                    if shadow_alpha == 0 {
-                        continue;
-                    }
+                        let shadow_alpha = ((alpha as f32 / 255.0) * shadow_opacity * 255.0) as u8;
+                        if shadow_alpha == 0 {
+                            continue;
+                        }
 
-                    let dst = *output.get_pixel(x, y);
-                    output.put_pixel(x, y, source_over(Rgba([0, 0, 0, shadow_alpha]), dst));
-                }
-            }
+                        let blended =
+                            source_over(Rgba([0, 0, 0, shadow_alpha]), pixel(dst), blending);
+                        dst.copy_from_slice(&blended.0);
+                    }
+                });
         }
     }
 
-    composite_image_onto(&mut output, card, margin, margin);
+    (output, margin)
+}
+
+/// Compose a drop-shadow behind `card` and return the final image.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_outer_shadow(
+    card: &RgbaImage,
+    shadow_blur: f32,
+    shadow_opacity: f32,
+    offset_x: i32,
+    offset_y: i32,
+    outer_bg: Rgba<u8>,
+    outer_padding: u32,
+    blending: Blending,
+) -> RgbaImage {
+    let (mut output, margin) = shadow_backdrop(
+        card,
+        shadow_blur,
+        shadow_opacity,
+        offset_x,
+        offset_y,
+        outer_bg,
+        outer_padding,
+        blending,
+    );
+    composite_image_onto(&mut output, card, margin, margin, blending);
     output
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{apply_outer_shadow, boxes_for_gauss, composite_image_onto, source_over};
-    use image::{ImageBuffer, Rgba};
+    use super::{apply_outer_shadow, boxes_for_gauss, composite_image_onto};
+    use crate::input::Blending;
+    use crate::utils::apply_rounded_corners;
+    use image::{ImageBuffer, Rgba, RgbaImage};
 
     #[test]
     fn gaussian_boxes_are_stable() {
@@ -197,22 +254,11 @@ mod tests {
         assert_eq!(boxes_for_gauss(1.5), [3, 3, 3]);
     }
 
-    #[test]
-    fn source_over_matches_expected_alpha_blend() {
-        let src = Rgba([200, 100, 50, 128]);
-        let dst = Rgba([10, 20, 30, 255]);
-        let out = source_over(src, dst);
-
-        assert_eq!(out[3], 255);
-        assert!(out[0] > dst[0]);
-        assert!(out[1] > dst[1]);
-    }
-
     #[test]
     fn composite_image_onto_respects_offsets() {
         let mut base = ImageBuffer::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
         let overlay = ImageBuffer::from_pixel(1, 1, Rgba([255, 0, 0, 255]));
-        composite_image_onto(&mut base, &overlay, 2, 1);
+        composite_image_onto(&mut base, &overlay, 2, 1, Blending::Linear);
 
         assert_eq!(*base.get_pixel(2, 1), Rgba([255, 0, 0, 255]));
         assert_eq!(*base.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
@@ -221,10 +267,60 @@ mod tests {
     #[test]
     fn outer_shadow_keeps_card_visible_without_shadow() {
         let card = ImageBuffer::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
-        let output = apply_outer_shadow(&card, 0.0, 0.0, 0, 0, Rgba([1, 2, 3, 255]), 1);
+        let output = apply_outer_shadow(
+            &card,
+            0.0,
+            0.0,
+            0,
+            0,
+            Rgba([1, 2, 3, 255]),
+            1,
+            Blending::Linear,
+        );
 
         assert_eq!(output.dimensions(), (4, 4));
         assert_eq!(*output.get_pixel(1, 1), Rgba([255, 255, 255, 255]));
         assert_eq!(*output.get_pixel(0, 0), Rgba([1, 2, 3, 255]));
     }
+
+    #[test]
+    fn srgb_blending_reproduces_the_original_output() {
+        let mut card: RgbaImage = ImageBuffer::from_fn(8, 6, |x, y| {
+            Rgba([(x * 37 + y * 11) as u8, (x * 53) as u8, (y * 71) as u8, 230])
+        });
+        apply_rounded_corners(&mut card, 3, Blending::Srgb);
+        let mut output = apply_outer_shadow(
+            &card,
+            1.5,
+            0.5,
+            0,
+            1,
+            Rgba([40, 60, 80, 200]),
+            2,
+            Blending::Srgb,
+        );
+        apply_rounded_corners(&mut output, 3, Blending::Srgb);
+
+        // Pixels and FNV-1a hash of the same steps run through the
+        // pre-`blending` shadow.rs and utils.rs.
+        let expected = [
+            ((0, 0), [40, 60, 80, 22]),
+            ((1, 0), [40, 60, 80, 101]),
+            ((0, 2), [40, 60, 80, 133]),
+            ((5, 13), [37, 56, 74, 202]),
+            ((8, 8), [46, 52, 69, 250]),
+            ((15, 16), [31, 47, 63, 209]),
+        ];
+        for ((x, y), pixel) in expected {
+            assert_eq!(output.get_pixel(x, y).0, pixel, "pixel ({x}, {y})");
+        }
+        let hash = output
+            .as_raw()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        assert_eq!(output.dimensions(), (22, 20));
+        assert_eq!(hash, 0x6d59_774f_b305_7c4b);
+    }
 }
@@ -0,0 +1,532 @@
+//! Before→after transition between two versions of a snippet.
+//!
+//! Lines are matched with a longest common subsequence, then the tokens of
+//! the changed hunks are matched the same way. Shared tokens slide to their
+//! new place while removed tokens fade out and added ones fade in.
+
+use ab_glyph::{Font, FontVec, ScaleFont};
+use image::{Rgba, RgbaImage};
+
+use crate::blend::mix;
+use crate::glyphs::{draw_text, text_advance, GlyphCache};
+use crate::render::{PreparedLine, RenderConfig};
+use crate::{Config, Line};
+
+/// Longest common subsequences are only computed when the table stays below
+/// this many cells; larger hunks simply fade.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// Byte ranges of the visible tokens of `text`: words, and every other
+/// non-whitespace character on its own.
+fn tokens(text: &str) -> Vec<(usize, usize)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut tokens: Vec<(usize, usize)> = Vec::new();
+    let mut in_word = false;
+
+    for (idx, c) in text.char_indices() {
+        let end = idx + c.len_utf8();
+        if c.is_whitespace() {
+            in_word = false;
+        } else if is_word(c) && in_word {
+            if let Some(last) = tokens.last_mut() {
+                last.1 = end;
+            }
+        } else {
+            tokens.push((idx, end));
+            in_word = is_word(c);
+        }
+    }
+    tokens
+}
+
+/// Index pairs of a longest common subsequence of `a` and `b`, or none when
+/// the inputs are too large to compare.
+fn lcs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len(), b.len());
+    if n == 0 || m == 0 || n.saturating_mul(m) > MAX_LCS_CELLS {
+        return Vec::new();
+    }
+
+    // lengths[i * (m + 1) + j] is the LCS length of a[i..] and b[j..].
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * (m + 1) + j] = if a[i] == b[j] {
+                lengths[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lengths[(i + 1) * (m + 1) + j].max(lengths[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * (m + 1) + j] >= lengths[i * (m + 1) + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// A token in one version, with the top-left corner of its first glyph.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Placed {
+    line: usize,
+    start: usize,
+    end: usize,
+    x: f32,
+    y: i32,
+}
+
+/// Where a token is before and after; `None` when it only exists in one version.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Move {
+    from: Option<Placed>,
+    to: Option<Placed>,
+}
+
+/// Tokens of one version of the snippet, positioned on the card.
+struct Version<'a> {
+    lines: &'a [Line],
+    prepared: Vec<PreparedLine>,
+    placed: Vec<Vec<Placed>>,
+}
+
+impl<'a> Version<'a> {
+    fn new<F, SF>(
+        lines: &'a [Line],
+        prepared: Vec<PreparedLine>,
+        line_number_width: f32,
+        render: &RenderConfig,
+        scaled_font: &SF,
+    ) -> Self
+    where
+        F: Font,
+        SF: ScaleFont<F>,
+    {
+        let left = render.scaled_padding as f32 + line_number_width;
+        let placed = lines
+            .iter()
+            .enumerate()
+            .map(|(line, text)| {
+                let y = render.line_top(line);
+                tokens(&text.text)
+                    .into_iter()
+                    .map(|(start, end)| Placed {
+                        line,
+                        start,
+                        end,
+                        x: left + text_advance(&text.text[..start], scaled_font),
+                        y,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            lines,
+            prepared,
+            placed,
+        }
+    }
+
+    fn text(&self, token: &Placed) -> &str {
+        &self.lines[token.line].text[token.start..token.end]
+    }
+}
+
+/// Match the tokens of an unmatched hunk of lines by their text.
+fn match_hunk(
+    before: &Version,
+    after: &Version,
+    lines_before: std::ops::Range<usize>,
+    lines_after: std::ops::Range<usize>,
+    moves: &mut Vec<Move>,
+) {
+    let from: Vec<Placed> = before.placed[lines_before].concat();
+    let to: Vec<Placed> = after.placed[lines_after].concat();
+    let from_text: Vec<&str> = from.iter().map(|token| before.text(token)).collect();
+    let to_text: Vec<&str> = to.iter().map(|token| after.text(token)).collect();
+
+    let mut matched_from = vec![false; from.len()];
+    let mut matched_to = vec![false; to.len()];
+    for (i, j) in lcs(&from_text, &to_text) {
+        matched_from[i] = true;
+        matched_to[j] = true;
+        moves.push(Move {
+            from: Some(from[i]),
+            to: Some(to[j]),
+        });
+    }
+
+    let removed = from
+        .iter()
+        .zip(&matched_from)
+        .filter(|(_, &matched)| !matched);
+    moves.extend(removed.map(|(&token, _)| Move {
+        from: Some(token),
+        to: None,
+    }));
+    let added = to.iter().zip(&matched_to).filter(|(_, &matched)| !matched);
+    moves.extend(added.map(|(&token, _)| Move {
+        from: None,
+        to: Some(token),
+    }));
+}
+
+fn diff(before: &Version, after: &Version) -> Vec<Move> {
+    let before_text: Vec<&str> = before.lines.iter().map(|line| line.text.as_str()).collect();
+    let after_text: Vec<&str> = after.lines.iter().map(|line| line.text.as_str()).collect();
+
+    let mut moves = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let ends = [(before.lines.len(), after.lines.len())];
+    for &(next_i, next_j) in lcs(&before_text, &after_text).iter().chain(&ends) {
+        match_hunk(before, after, i..next_i, j..next_j, &mut moves);
+        if next_i < before.lines.len() {
+            let pairs = before.placed[next_i].iter().zip(&after.placed[next_j]);
+            moves.extend(pairs.map(|(&from, &to)| Move {
+                from: Some(from),
+                to: Some(to),
+            }));
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    moves
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// `from` at 0 and exactly `to` at 1.
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from * (1.0 - t) + to * t
+}
+
+/// Token moves between two versions, drawn at any point of the transition.
+pub(crate) struct Transition<'a> {
+    before: Version<'a>,
+    after: Version<'a>,
+    moves: Vec<Move>,
+}
+
+impl<'a> Transition<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new<F, SF>(
+        before: &'a [Line],
+        before_prepared: Vec<PreparedLine>,
+        before_line_numbers: f32,
+        after: &'a [Line],
+        after_prepared: Vec<PreparedLine>,
+        after_line_numbers: f32,
+        render: &RenderConfig,
+        scaled_font: &SF,
+    ) -> Self
+    where
+        F: Font,
+        SF: ScaleFont<F>,
+    {
+        let before = Version::new(
+            before,
+            before_prepared,
+            before_line_numbers,
+            render,
+            scaled_font,
+        );
+        let after = Version::new(
+            after,
+            after_prepared,
+            after_line_numbers,
+            render,
+            scaled_font,
+        );
+        let moves = diff(&before, &after);
+        Self {
+            before,
+            after,
+            moves,
+        }
+    }
+
+    /// Draw the snippet `t` of the way (0 to 1) from the old to the new version.
+    /// Removed tokens fade out during the first half and added ones fade in
+    /// during the second, while shared tokens slide the whole way.
+    pub(crate) fn draw(
+        &self,
+        card: &mut RgbaImage,
+        t: f32,
+        config: &Config,
+        render: &RenderConfig,
+        font: &FontVec,
+        glyphs: &GlyphCache,
+    ) {
+        let eased = smoothstep(t.clamp(0.0, 1.0));
+        let fade_out = (1.0 - 2.0 * t).clamp(0.0, 1.0);
+        let fade_in = (2.0 * t - 1.0).clamp(0.0, 1.0);
+
+        if config.line_numbers {
+            let rows_before = self.before.lines.len();
+            let rows_after = self.after.lines.len();
+            for row in 0..rows_before.max(rows_after) {
+                let opacity = match (row < rows_before, row < rows_after) {
+                    (true, true) => 1.0,
+                    (true, false) => fade_out,
+                    _ => fade_in,
+                };
+                let y = render.line_top(row);
+                let text = format!("{:>4}  ", config.start_line + row);
+                let color = fade(render.line_number_color, opacity, config, render);
+                if opacity > 0.0 {
+                    let x = render.scaled_padding as f32;
+                    draw_text(
+                        card,
+                        glyphs,
+                        font,
+                        render.scale,
+                        color,
+                        config.blending,
+                        x,
+                        y,
+                        &text,
+                    );
+                }
+            }
+        }
+
+        for motion in &self.moves {
+            match (motion.from, motion.to) {
+                (Some(from), Some(to)) => {
+                    let x = lerp(from.x, to.x, eased);
+                    let y = lerp(from.y as f32, to.y as f32, eased) as i32;
+                    let (version, token) = if eased < 0.5 {
+                        (&self.before, from)
+                    } else {
+                        (&self.after, to)
+                    };
+                    let placed = Placed { x, y, ..token };
+                    draw_token(card, version, &placed, 1.0, config, render, font, glyphs);
+                }
+                (Some(from), None) => {
+                    draw_token(
+                        card,
+                        &self.before,
+                        &from,
+                        fade_out,
+                        config,
+                        render,
+                        font,
+                        glyphs,
+                    );
+                }
+                (None, Some(to)) => {
+                    draw_token(
+                        card,
+                        &self.after,
+                        &to,
+                        fade_in,
+                        config,
+                        render,
+                        font,
+                        glyphs,
+                    );
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+/// `color` as it looks at `opacity` over the card background.
+fn fade(color: Rgba<u8>, opacity: f32, config: &Config, render: &RenderConfig) -> Rgba<u8> {
+    if opacity >= 1.0 {
+        return color;
+    }
+    mix(render.bg_color, color, opacity, config.blending)
+}
+
+/// Draw `token` with the highlight segments it overlaps.
+#[allow(clippy::too_many_arguments)]
+fn draw_token(
+    card: &mut RgbaImage,
+    version: &Version,
+    token: &Placed,
+    opacity: f32,
+    config: &Config,
+    render: &RenderConfig,
+    font: &FontVec,
+    glyphs: &GlyphCache,
+) {
+    if opacity <= 0.0 {
+        return;
+    }
+
+    let text = &version.lines[token.line].text;
+    let mut x = token.x;
+    for segment in &version.prepared[token.line].segments {
+        let start = segment.start.max(token.start);
+        let end = segment.end.min(token.end);
+        if start >= end {
+            continue;
+        }
+        x = draw_text(
+            card,
+            glyphs,
+            font,
+            render.scale,
+            fade(segment.color, opacity, config, render),
+            config.blending,
+            x,
+            token.y,
+            &text[start..end],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, lcs, tokens, Placed, Version};
+    use crate::Line;
+
+    fn lines(texts: &[&str]) -> Vec<Line> {
+        texts
+            .iter()
+            .map(|text| Line {
+                text: text.to_string(),
+                spans: Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Version with each token placed at its byte offset on its line's row.
+    fn version(lines: &[Line]) -> Version<'_> {
+        let placed = lines
+            .iter()
+            .enumerate()
+            .map(|(line, text)| {
+                tokens(&text.text)
+                    .into_iter()
+                    .map(|(start, end)| Placed {
+                        line,
+                        start,
+                        end,
+                        x: start as f32,
+                        y: line as i32,
+                    })
+                    .collect()
+            })
+            .collect();
+        Version {
+            lines,
+            prepared: Vec::new(),
+            placed,
+        }
+    }
+
+    type Side<'a> = Option<(usize, &'a str)>;
+
+    fn moves<'a>(before: &'a Version, after: &'a Version) -> Vec<(Side<'a>, Side<'a>)> {
+        let side = |version: &'a Version, token: Option<Placed>| {
+            token.map(|token| (token.line, version.text(&token)))
+        };
+        diff(before, after)
+            .into_iter()
+            .map(|motion| (side(before, motion.from), side(after, motion.to)))
+            .collect()
+    }
+
+    #[test]
+    fn tokens_split_words_and_punctuation() {
+        let text = "  let é_1 = f(x);";
+        let words: Vec<_> = tokens(text)
+            .into_iter()
+            .map(|(start, end)| &text[start..end])
+            .collect();
+        assert_eq!(words, vec!["let", "é_1", "=", "f", "(", "x", ")", ";"]);
+    }
+
+    #[test]
+    fn lcs_pairs_common_items_in_order() {
+        let before = ["a", "b", "c", "d"];
+        let after = ["a", "c", "x", "d"];
+        assert_eq!(lcs(&before, &after), vec![(0, 0), (2, 1), (3, 3)]);
+        assert!(lcs::<&str>(&[], &after).is_empty());
+    }
+
+    #[test]
+    fn diff_keeps_unchanged_lines_and_matches_tokens_of_changed_ones() {
+        let before = lines(&["a {", "x + 1", "}"]);
+        let after = lines(&["b", "a {", "y + 1", "}"]);
+        let (before, after) = (version(&before), version(&after));
+
+        assert_eq!(
+            moves(&before, &after),
+            vec![
+                (None, Some((0, "b"))),
+                (Some((0, "a")), Some((1, "a"))),
+                (Some((0, "{")), Some((1, "{"))),
+                (Some((1, "+")), Some((2, "+"))),
+                (Some((1, "1")), Some((2, "1"))),
+                (Some((1, "x")), None),
+                (None, Some((2, "y"))),
+                (Some((2, "}")), Some((3, "}"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_of_identical_versions_moves_nothing() {
+        let text = lines(&["let x = 1;", "", "f(x)"]);
+        let version = version(&text);
+
+        let moves = diff(&version, &version);
+        assert_eq!(moves.len(), 9);
+        assert!(moves
+            .iter()
+            .all(|motion| motion.from.is_some() && motion.from == motion.to));
+    }
+
+    /// Needs a font: point `SNAPSHOT_TEST_FONT` at a TTF file.
+    #[test]
+    fn last_transition_frame_matches_the_after_render() {
+        use crate::animation::Frames;
+        use crate::font::font_from_bytes;
+        use crate::glyphs::GlyphCache;
+        use crate::render::render_with_cache;
+        use crate::Input;
+
+        let Ok(path) = std::env::var("SNAPSHOT_TEST_FONT") else {
+            eprintln!("SNAPSHOT_TEST_FONT is not set, skipping");
+            return;
+        };
+        let font = font_from_bytes(std::fs::read(path).unwrap()).unwrap();
+        let glyphs = GlyphCache::new();
+        let span = |start, end, fg| serde_json::json!({ "start": start, "end": end, "fg": fg });
+        let input: Input = serde_json::from_value(serde_json::json!({
+            "lines": [
+                { "text": "fn main() {", "spans": [span(0, 2, "#c678dd")] },
+                { "text": "    let total = 1 + 2;", "spans": [span(4, 7, "#c678dd")] },
+                { "text": "    println!(\"{total}\");", "spans": [span(4, 12, "#61afef")] },
+                { "text": "}", "spans": [] },
+            ],
+            "before": [
+                { "text": "fn main() {", "spans": [span(0, 2, "#c678dd")] },
+                { "text": "    let x = 1;", "spans": [span(4, 7, "#c678dd")] },
+                { "text": "}", "spans": [] },
+            ],
+            "config": { "scale": 1.5, "line_height": 19.3, "border_radius": 6 },
+        }))
+        .unwrap();
+
+        let frames = Frames::new(&input, &font, &glyphs);
+        let last = frames.iter().last().unwrap().image;
+        assert!(last == render_with_cache(&input, &font, &glyphs));
+    }
+}
@@ -1,6 +1,7 @@
-use ab_glyph::{Font, ScaleFont};
 use image::{Rgba, RgbaImage};
 
+use crate::input::Blending;
+
 const HEX_FALLBACK: Rgba<u8> = Rgba([255, 255, 255, 255]);
 
 fn parse_hex_component(component: &str) -> Option<u8> {
@@ -11,6 +12,7 @@ fn parse_hex_component(component: &str) -> Option<u8> {
     u8::from_str_radix(component, 16).ok()
 }
 
+/// Parse `#rrggbb` or `#rrggbbaa` (the `#` is optional).
 pub fn try_hex_to_rgba(hex: &str) -> Option<Rgba<u8>> {
     let hex = hex.trim().trim_start_matches('#');
 
@@ -31,24 +33,14 @@ pub fn try_hex_to_rgba(hex: &str) -> Option<Rgba<u8>> {
     }
 }
 
+/// Like [`try_hex_to_rgba`], falling back to white for invalid colors.
 pub fn hex_to_rgba(hex: &str) -> Rgba<u8> {
     try_hex_to_rgba(hex).unwrap_or(HEX_FALLBACK)
 }
 
-pub fn measure_text_width<F, SF>(text: &str, font: &SF) -> u32
-where
-    F: Font,
-    SF: ScaleFont<F>,
-{
-    let width = text.chars().fold(0.0, |acc, c| {
-        let glyph_id = font.glyph_id(c);
-        acc + font.h_advance(glyph_id)
-    });
-
-    width.ceil() as u32
-}
-
-pub fn apply_rounded_corners(img: &mut RgbaImage, radius: u32) {
+/// Make the corners of `img` transparent with an anti-aliased edge, rounding
+/// its alpha the way `blending` composites.
+pub fn apply_rounded_corners(img: &mut RgbaImage, radius: u32, blending: Blending) {
     let (width, height) = img.dimensions();
     let radius = radius.min(width / 2).min(height / 2);
     if radius == 0 {
@@ -65,13 +57,27 @@ pub fn apply_rounded_corners(img: &mut RgbaImage, radius: u32) {
         height as f32 - radius as f32 - 0.5,
     );
 
-    apply_corner(img, 0, 0, radius, top_left);
-    apply_corner(img, right_start, 0, radius, top_right);
-    apply_corner(img, 0, bottom_start, radius, bottom_left);
-    apply_corner(img, right_start, bottom_start, radius, bottom_right);
+    apply_corner(img, 0, 0, radius, top_left, blending);
+    apply_corner(img, right_start, 0, radius, top_right, blending);
+    apply_corner(img, 0, bottom_start, radius, bottom_left, blending);
+    apply_corner(
+        img,
+        right_start,
+        bottom_start,
+        radius,
+        bottom_right,
+        blending,
+    );
 }
 
-fn apply_corner(img: &mut RgbaImage, start_x: u32, start_y: u32, radius: u32, center: (f32, f32)) {
+fn apply_corner(
+    img: &mut RgbaImage,
+    start_x: u32,
+    start_y: u32,
+    radius: u32,
+    center: (f32, f32),
+    blending: Blending,
+) {
     let radius_f = radius as f32;
 
     for y in start_y..start_y + radius {
@@ -88,16 +94,60 @@ fn apply_corner(img: &mut RgbaImage, start_x: u32, start_y: u32, radius: u32, ce
             if distance > radius_f {
                 pixel[3] = 0;
             } else {
-                let alpha = ((radius_f - distance) / 1.5).clamp(0.0, 1.0);
-                pixel[3] = (pixel[3] as f32 * alpha) as u8;
+                let alpha = pixel[3] as f32 * ((radius_f - distance) / 1.5).clamp(0.0, 1.0);
+                pixel[3] = match blending {
+                    Blending::Srgb => alpha as u8,
+                    Blending::Linear => alpha.round() as u8,
+                };
             }
         }
     }
 }
 
+/// Replace every `{name}` in `template` with `value(name)`, keeping
+/// placeholders it returns `None` for as written.
+pub fn fill_template(template: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+        filled.push_str(&rest[..open]);
+        match value(&rest[open + 1..close]) {
+            Some(replacement) => filled.push_str(&replacement),
+            None => filled.push_str(&rest[open..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Standard padded base64, as used by terminal image protocols and data URIs.
+pub fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (idx, &byte)| {
+            group | (byte as u32) << (16 - 8 * idx)
+        });
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * idx) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{apply_rounded_corners, hex_to_rgba, try_hex_to_rgba};
+    use super::{apply_rounded_corners, base64, fill_template, hex_to_rgba, try_hex_to_rgba};
+    use crate::input::Blending;
     use image::{ImageBuffer, Rgba};
 
     #[test]
@@ -122,10 +172,40 @@ mod tests {
     #[test]
     fn rounded_corners_only_change_corner_pixels() {
         let mut image = ImageBuffer::from_pixel(8, 8, Rgba([1, 2, 3, 255]));
-        apply_rounded_corners(&mut image, 3);
+        apply_rounded_corners(&mut image, 3, Blending::Linear);
 
         assert!(image.get_pixel(0, 0)[3] < 255);
         assert_eq!(image.get_pixel(4, 4)[3], 255);
         assert!(image.get_pixel(2, 0)[3] < 255);
     }
+
+    #[test]
+    fn corner_alpha_follows_blending() {
+        let mut linear = ImageBuffer::from_pixel(8, 8, Rgba([1, 2, 3, 255]));
+        let mut srgb = linear.clone();
+        apply_rounded_corners(&mut linear, 3, Blending::Linear);
+        apply_rounded_corners(&mut srgb, 3, Blending::Srgb);
+
+        // The edge pixel is 50.93% covered, 129.87 of 255: linear rounds it
+        // as it rounds composited alpha, srgb truncates.
+        assert_eq!(linear.get_pixel(1, 0)[3], 130);
+        assert_eq!(srgb.get_pixel(1, 0)[3], 129);
+    }
+
+    #[test]
+    fn templates_keep_unknown_placeholders() {
+        let filled = fill_template("{a}-{b}-{a}{", |name| {
+            (name == "a").then(|| "x".to_string())
+        });
+        assert_eq!(filled, "x-{b}-x{");
+    }
+
+    #[test]
+    fn base64_pads_partial_groups() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xfb, 0xff]), "+/8=");
+    }
 }
@@ -0,0 +1,600 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+use crate::animation::{MAX_FPS, MAX_SECONDS, MIN_SPEED};
+use crate::protocol::check_protocol_version;
+use crate::utils::{fill_template, try_hex_to_rgba};
+use crate::{Config, Input, Line};
+
+pub const INPUT_KEYS: &[&str] = &["protocol_version", "lines", "config", "before", "source"];
+
+pub const CONFIG_KEYS: &[&str] = &[
+    "snapshot_dir",
+    "output_path",
+    "filename_template",
+    "output_encoding",
+    "overwrite",
+    "format",
+    "scale",
+    "dpi",
+    "padding",
+    "line_height",
+    "font_size",
+    "background",
+    "foreground",
+    "clipboard",
+    "save",
+    "clipboard_backend",
+    "shadow",
+    "line_numbers",
+    "start_line",
+    "border_radius",
+    "outer_background",
+    "outer_padding",
+    "blending",
+    "quality",
+    "webp_lossless",
+    "avif_speed",
+    "png_compression",
+    "embed_source",
+    "html_card",
+    "animation",
+    "strict",
+];
+
+pub const FILENAME_PLACEHOLDERS: &[&str] = &[
+    "date",
+    "time",
+    "file",
+    "lang",
+    "start_line",
+    "end_line",
+    "hash",
+    "ext",
+];
+
+pub const ANIMATION_KEYS: &[&str] = &["reveal", "fps", "speed", "hold", "cursor", "duration"];
+
+/// Accepted ranges of bounded whole-number options.
+const QUALITY_RANGE: (u64, u64) = (1, 100);
+const AVIF_SPEED_RANGE: (u64, u64) = (1, 10);
+const FPS_RANGE: (u64, u64) = (1, MAX_FPS as u64);
+
+/// Shape a numeric value must have for typed deserialization to accept it.
+#[derive(Clone, Copy)]
+enum Number {
+    /// A whole number from 0 to the given maximum.
+    Whole(u64),
+    /// A whole number in an option's accepted range, which is narrower than
+    /// its type.
+    Ranged((u64, u64)),
+    Real,
+    OptionalReal,
+}
+
+const CONFIG_NUMBERS: &[(&str, Number)] = &[
+    ("scale", Number::Real),
+    ("dpi", Number::OptionalReal),
+    ("padding", Number::Whole(u32::MAX as u64)),
+    ("line_height", Number::Real),
+    ("font_size", Number::Real),
+    ("start_line", Number::Whole(u32::MAX as u64)),
+    ("border_radius", Number::Whole(u32::MAX as u64)),
+    ("outer_padding", Number::Whole(u32::MAX as u64)),
+    ("quality", Number::Ranged(QUALITY_RANGE)),
+    ("avif_speed", Number::Ranged(AVIF_SPEED_RANGE)),
+];
+
+const ANIMATION_NUMBERS: &[(&str, Number)] = &[
+    ("fps", Number::Ranged(FPS_RANGE)),
+    ("speed", Number::OptionalReal),
+    ("hold", Number::Real),
+    ("duration", Number::Real),
+];
+
+/// A single problem found in the input, located by a JSON-style path.
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub path: String,
+    pub message: String,
+}
+
+impl Issue {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Suggest the closest known key for a misspelled one.
+fn suggest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|candidate| (strsim::levenshtein(key, candidate), *candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn check_unknown_keys(raw: &Value, known: &[&str], prefix: &str, issues: &mut Vec<Issue>) {
+    let Some(object) = raw.as_object() else {
+        return;
+    };
+
+    for key in object.keys() {
+        if known.contains(&key.as_str()) {
+            continue;
+        }
+
+        let path = format!("{prefix}{key}");
+        let message = match suggest(key, known) {
+            Some(candidate) => format!("unknown key (did you mean `{candidate}`?)"),
+            None => "unknown key".to_string(),
+        };
+        issues.push(Issue::new(path, message));
+    }
+}
+
+fn check_color(value: &str, path: impl Into<String>, issues: &mut Vec<Issue>) {
+    if try_hex_to_rgba(value).is_none() {
+        issues.push(Issue::new(
+            path,
+            format!("invalid color `{value}` (expected #rrggbb or #rrggbbaa)"),
+        ));
+    }
+}
+
+fn check_positive(value: f32, path: &str, issues: &mut Vec<Issue>) {
+    if !(value.is_finite() && value > 0.0) {
+        issues.push(Issue::new(
+            path,
+            format!("must be a positive number, got {value}"),
+        ));
+    }
+}
+
+fn check_at_most_seconds(value: f32, path: &str, issues: &mut Vec<Issue>) {
+    if value.is_finite() && value > MAX_SECONDS {
+        issues.push(Issue::new(
+            path,
+            format!("must be at most {MAX_SECONDS} seconds, got {value}"),
+        ));
+    }
+}
+
+fn out_of_range(value: impl fmt::Display, (min, max): (u64, u64)) -> String {
+    format!("must be between {min} and {max}, got {value}")
+}
+
+fn check_range(value: u64, range: (u64, u64), path: &str, issues: &mut Vec<Issue>) {
+    if !(range.0..=range.1).contains(&value) {
+        issues.push(Issue::new(path, out_of_range(value, range)));
+    }
+}
+
+fn number_problem(value: &Value, expected: Number) -> Option<String> {
+    match (expected, value) {
+        (Number::OptionalReal, Value::Null) => None,
+        (Number::Real | Number::OptionalReal, Value::Number(_)) => None,
+        (Number::Whole(max), Value::Number(number)) => match (number.as_u64(), number.as_i64()) {
+            (Some(whole), _) if whole <= max => None,
+            (Some(whole), _) => Some(format!("must be at most {max}, got {whole}")),
+            (None, Some(negative)) => Some(format!("must not be negative, got {negative}")),
+            (None, None) => Some(format!("must be a whole number, got {number}")),
+        },
+        (Number::Ranged(range), Value::Number(number)) => match number.as_u64() {
+            Some(whole) if (range.0..=range.1).contains(&whole) => None,
+            _ if number.is_f64() => Some(format!("must be a whole number, got {number}")),
+            _ => Some(out_of_range(number, range)),
+        },
+        _ => Some(format!("must be a number, got {value}")),
+    }
+}
+
+fn take_invalid(object: &mut Value, numbers: &[(&str, Number)], prefix: &str) -> Vec<Issue> {
+    let Some(object) = object.as_object_mut() else {
+        return Vec::new();
+    };
+
+    let mut issues = Vec::new();
+    for &(key, expected) in numbers {
+        let Some(message) = object
+            .get(key)
+            .and_then(|value| number_problem(value, expected))
+        else {
+            continue;
+        };
+        issues.push(Issue::new(format!("{prefix}{key}"), message));
+        object.remove(key);
+    }
+    issues
+}
+
+/// Remove config numbers of the wrong type or range from `raw`, which typed
+/// deserialization would reject one at a time without a path, and return an
+/// issue for each.
+pub fn take_invalid_numbers(raw: &mut Value) -> Vec<Issue> {
+    let Some(config) = raw.get_mut("config") else {
+        return Vec::new();
+    };
+    let mut issues = take_invalid(config, CONFIG_NUMBERS, "config.");
+    if let Some(animation) = config.get_mut("animation") {
+        issues.extend(take_invalid(
+            animation,
+            ANIMATION_NUMBERS,
+            "config.animation.",
+        ));
+    }
+    issues
+}
+
+fn check_lines(lines: &[Line], prefix: &str, issues: &mut Vec<Issue>) {
+    for (line_idx, line) in lines.iter().enumerate() {
+        for (span_idx, span) in line.spans.iter().enumerate() {
+            let path = format!("{prefix}[{line_idx}].spans[{span_idx}]");
+            if let Some(fg) = &span.fg {
+                check_color(fg, format!("{path}.fg"), issues);
+            }
+            if let Some(bg) = &span.bg {
+                check_color(bg, format!("{path}.bg"), issues);
+            }
+        }
+    }
+}
+
+fn check_config(config: &Config, line_count: usize, issues: &mut Vec<Issue>) {
+    check_color(&config.background, "config.background", issues);
+    check_color(&config.foreground, "config.foreground", issues);
+    check_color(&config.outer_background, "config.outer_background", issues);
+
+    check_positive(config.scale, "config.scale", issues);
+    if let Some(dpi) = config.dpi {
+        check_positive(dpi, "config.dpi", issues);
+    }
+    check_positive(config.font_size, "config.font_size", issues);
+    check_positive(config.line_height, "config.line_height", issues);
+
+    check_range(
+        config.quality.into(),
+        QUALITY_RANGE,
+        "config.quality",
+        issues,
+    );
+    check_range(
+        config.avif_speed.into(),
+        AVIF_SPEED_RANGE,
+        "config.avif_speed",
+        issues,
+    );
+
+    if let Some(animation) = &config.animation {
+        check_range(
+            animation.fps.into(),
+            FPS_RANGE,
+            "config.animation.fps",
+            issues,
+        );
+        if let Some(speed) = animation.speed {
+            check_positive(speed, "config.animation.speed", issues);
+            if speed > 0.0 && speed < MIN_SPEED {
+                issues.push(Issue::new(
+                    "config.animation.speed",
+                    format!("must be at least {MIN_SPEED} per second, got {speed}"),
+                ));
+            }
+        }
+        check_positive(animation.duration, "config.animation.duration", issues);
+        check_at_most_seconds(animation.duration, "config.animation.duration", issues);
+        if !(animation.hold.is_finite() && animation.hold >= 0.0) {
+            issues.push(Issue::new(
+                "config.animation.hold",
+                format!("must be zero or more seconds, got {}", animation.hold),
+            ));
+        }
+        check_at_most_seconds(animation.hold, "config.animation.hold", issues);
+    }
+
+    fill_template(&config.filename_template, |name| {
+        if !FILENAME_PLACEHOLDERS.contains(&name) {
+            let message = match suggest(name, FILENAME_PLACEHOLDERS) {
+                Some(candidate) => {
+                    format!("unknown placeholder {{{name}}} (did you mean {{{candidate}}}?)")
+                }
+                None => format!("unknown placeholder {{{name}}}"),
+            };
+            issues.push(Issue::new("config.filename_template", message));
+        }
+        None
+    });
+
+    if !config.save && !config.clipboard {
+        issues.push(Issue::new(
+            "config.save",
+            "false only skips the file when config.clipboard copies the snapshot, it is saved anyway",
+        ));
+    }
+
+    if config.start_line == 0 {
+        issues.push(Issue::new(
+            "config.start_line",
+            "line numbers start at 1, got 0",
+        ));
+    } else if config.start_line.checked_add(line_count).is_none() {
+        issues.push(Issue::new(
+            "config.start_line",
+            format!("{} is too large", config.start_line),
+        ));
+    }
+}
+
+/// Collect every problem in `input`. `raw` is the JSON the input was parsed
+/// from and is used to detect keys that serde would otherwise ignore.
+pub fn validate_input(input: &Input, raw: &Value) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    check_unknown_keys(raw, INPUT_KEYS, "", &mut issues);
+    if let Some(config) = raw.get("config") {
+        check_unknown_keys(config, CONFIG_KEYS, "config.", &mut issues);
+        if let Some(animation) = config.get("animation") {
+            check_unknown_keys(animation, ANIMATION_KEYS, "config.animation.", &mut issues);
+        }
+    }
+
+    check_config(&input.config, input.lines.len(), &mut issues);
+
+    check_lines(&input.lines, "lines", &mut issues);
+    if let Some(before) = &input.before {
+        check_lines(before, "before", &mut issues);
+        if input.config.animation.is_none() {
+            issues.push(Issue::new(
+                "before",
+                "only used by animations, set config.animation to animate the change",
+            ));
+        }
+    }
+
+    issues
+}
+
+/// Fail with every issue when `strict` is set, otherwise hand them back to be
+/// reported as warnings.
+pub fn enforce(issues: Vec<Issue>, strict: bool) -> Result<Vec<Issue>> {
+    if !strict || issues.is_empty() {
+        return Ok(issues);
+    }
+
+    Err(invalid_input(&issues))
+}
+
+fn invalid_input(issues: &[Issue]) -> anyhow::Error {
+    let details: Vec<_> = issues.iter().map(|issue| format!("  - {issue}")).collect();
+    anyhow!("Invalid input:\n{}", details.join("\n"))
+}
+
+/// Fail with `invalid` numbers together with the other `issues`, or enforce
+/// `issues` alone when every number was usable.
+pub fn enforce_with(invalid: Vec<Issue>, issues: Vec<Issue>, strict: bool) -> Result<Vec<Issue>> {
+    if invalid.is_empty() {
+        return enforce(issues, strict);
+    }
+    let all: Vec<_> = invalid.into_iter().chain(issues).collect();
+    Err(invalid_input(&all))
+}
+
+/// Parse and validate one input document, returning the issues to report as
+/// warnings (or failing on them in strict mode).
+pub fn parse_input(raw: &Value) -> Result<(Input, Vec<Issue>)> {
+    let mut checked = raw.clone();
+    let mut invalid = take_invalid_numbers(&mut checked);
+    let input = match Input::deserialize(&checked) {
+        Ok(input) => input,
+        Err(error) if invalid.is_empty() => {
+            return Err(error).context("Failed to parse JSON input");
+        }
+        Err(error) => {
+            invalid.push(Issue::new("input", error.to_string()));
+            return Err(invalid_input(&invalid));
+        }
+    };
+    check_protocol_version(input.protocol_version)?;
+    let issues = enforce_with(invalid, validate_input(&input, raw), input.config.strict)?;
+    Ok((input, issues))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enforce, parse_input, suggest, validate_input, Issue, CONFIG_KEYS};
+    use crate::Input;
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    fn validate(raw: Value) -> Vec<String> {
+        let input = Input::deserialize(&raw).expect("test input should parse");
+        validate_input(&input, &raw)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn valid_input_has_no_issues() {
+        let issues = validate(json!({
+            "lines": [{ "text": "let x = 1;", "spans": [{ "start": 0, "end": 3, "fg": "#c678dd" }] }],
+            "config": { "background": "#282c34", "scale": 2 }
+        }));
+
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn reports_all_problems_together() {
+        let issues = validate(json!({
+            "lines": [{ "text": "x", "spans": [{ "start": 0, "end": 1, "fg": "red" }] }],
+            "config": { "background": "#12", "scale": 0, "font_size": -1, "start_line": 0 }
+        }));
+
+        assert_eq!(
+            issues,
+            vec![
+                "config.background: invalid color `#12` (expected #rrggbb or #rrggbbaa)",
+                "config.scale: must be a positive number, got 0",
+                "config.font_size: must be a positive number, got -1",
+                "config.start_line: line numbers start at 1, got 0",
+                "lines[0].spans[0].fg: invalid color `red` (expected #rrggbb or #rrggbbaa)",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_get_suggestions() {
+        let issues = validate(json!({
+            "lines": [],
+            "config": { "line_number": true, "zzz": 1 },
+            "extra": true
+        }));
+
+        assert_eq!(
+            issues,
+            vec![
+                "extra: unknown key",
+                "config.line_number: unknown key (did you mean `line_numbers`?)",
+                "config.zzz: unknown key",
+            ]
+        );
+    }
+
+    #[test]
+    fn animation_options_are_checked() {
+        let issues = validate(json!({
+            "lines": [],
+            "config": { "animation": { "fps": 0, "hold": -1, "curser": false } }
+        }));
+
+        assert_eq!(
+            issues,
+            vec![
+                "config.animation.curser: unknown key (did you mean `cursor`?)",
+                "config.animation.fps: must be between 1 and 100, got 0",
+                "config.animation.hold: must be zero or more seconds, got -1",
+            ]
+        );
+
+        let issues = validate(json!({
+            "lines": [],
+            "config": { "animation": { "speed": 0.001, "hold": 86400, "duration": 1e6 } }
+        }));
+
+        assert_eq!(
+            issues,
+            vec![
+                "config.animation.speed: must be at least 0.1 per second, got 0.001",
+                "config.animation.duration: must be at most 60 seconds, got 1000000",
+                "config.animation.hold: must be at most 60 seconds, got 86400",
+            ]
+        );
+    }
+
+    #[test]
+    fn filename_placeholders_are_checked() {
+        let issues = validate(json!({
+            "lines": [],
+            "config": { "filename_template": "{lang}-{hsah}-{commit}.{ext}" }
+        }));
+
+        assert_eq!(
+            issues,
+            vec![
+                "config.filename_template: unknown placeholder {hsah} (did you mean {hash}?)",
+                "config.filename_template: unknown placeholder {commit}",
+            ]
+        );
+    }
+
+    #[test]
+    fn before_needs_an_animation() {
+        let before = json!([{ "text": "old", "spans": [] }]);
+        let issues = validate(json!({ "lines": [], "config": {}, "before": before }));
+        assert_eq!(
+            issues,
+            vec!["before: only used by animations, set config.animation to animate the change"]
+        );
+
+        let animated = json!({ "lines": [], "config": { "animation": {} }, "before": before });
+        assert!(validate(animated).is_empty());
+    }
+
+    #[test]
+    fn skipping_the_file_needs_the_clipboard() {
+        let issues =
+            validate(json!({ "lines": [], "config": { "save": false, "clipboard": false } }));
+        assert_eq!(
+            issues,
+            vec!["config.save: false only skips the file when config.clipboard copies the snapshot, it is saved anyway"]
+        );
+        assert!(validate(json!({ "lines": [], "config": { "save": false } })).is_empty());
+    }
+
+    #[test]
+    fn suggestion_picks_closest_key() {
+        assert_eq!(suggest("paddding", CONFIG_KEYS), Some("padding"));
+        assert_eq!(suggest("outer_bg", CONFIG_KEYS), None);
+    }
+
+    #[test]
+    fn mistyped_numbers_are_reported_with_other_issues() {
+        let error = parse_input(&json!({
+            "lines": [],
+            "config": {
+                "padding": -5,
+                "outer_padding": -1,
+                "border_radius": "x",
+                "quality": 300,
+                "avif_speed": -3,
+                "scale": 0,
+                "animation": { "fps": 2.5 }
+            }
+        }))
+        .unwrap_err()
+        .to_string();
+
+        assert_eq!(
+            error,
+            "Invalid input:\n  \
+             - config.padding: must not be negative, got -5\n  \
+             - config.border_radius: must be a number, got \"x\"\n  \
+             - config.outer_padding: must not be negative, got -1\n  \
+             - config.quality: must be between 1 and 100, got 300\n  \
+             - config.avif_speed: must be between 1 and 10, got -3\n  \
+             - config.animation.fps: must be a whole number, got 2.5\n  \
+             - config.scale: must be a positive number, got 0"
+        );
+    }
+
+    #[test]
+    fn strict_mode_turns_issues_into_errors() {
+        let issues = || {
+            vec![Issue::new(
+                "config.scale",
+                "must be a positive number, got 0",
+            )]
+        };
+
+        assert_eq!(enforce(issues(), false).unwrap(), issues());
+        let error = enforce(issues(), true).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Invalid input:\n  - config.scale: must be a positive number, got 0"
+        );
+        assert!(enforce(Vec::new(), true).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+//! wasm-bindgen API for rendering snapshots in the browser.
+//!
+//! The host supplies the font bytes and the same JSON input the CLI reads on
+//! stdin; nothing touches the filesystem or clipboard.
+
+use ab_glyph::FontVec;
+use image::RgbaImage;
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+use crate::animation::Frames;
+use crate::encode::{encode_animation, encode_to_vec};
+use crate::html::render_html;
+use crate::validate::parse_input;
+use crate::{font_from_bytes, render_with_cache, GlyphCache, Input, OutputFormat};
+
+fn js_error(error: impl std::fmt::Display) -> JsError {
+    JsError::new(&format!("{error:#}"))
+}
+
+/// Raw RGBA8 pixels, row-major, `width * height * 4` bytes long.
+#[wasm_bindgen]
+pub struct RgbaBuffer {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl RgbaBuffer {
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Copy of the pixel data, suitable for `new ImageData(...)`.
+    #[wasm_bindgen(getter)]
+    pub fn data(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+
+/// Renderer holding a parsed font and its glyph cache, reused across calls.
+#[wasm_bindgen]
+pub struct Renderer {
+    font: FontVec,
+    glyphs: GlyphCache,
+}
+
+#[wasm_bindgen]
+impl Renderer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(font: Vec<u8>) -> Result<Renderer, JsError> {
+        let font = font_from_bytes(font).map_err(js_error)?;
+        Ok(Self {
+            font,
+            glyphs: GlyphCache::new(),
+        })
+    }
+
+    fn parse(input_json: &str) -> Result<Input, JsError> {
+        let raw: Value = serde_json::from_str(input_json).map_err(js_error)?;
+        let (input, _) = parse_input(&raw).map_err(js_error)?;
+        Ok(input)
+    }
+
+    fn render(&self, input: &Input) -> RgbaImage {
+        render_with_cache(input, &self.font, &self.glyphs)
+    }
+
+    /// Render `input_json` and return the PNG-encoded image, or an animated
+    /// PNG when `config.animation` is set.
+    #[wasm_bindgen(js_name = renderPng)]
+    pub fn render_png(&self, input_json: &str) -> Result<Vec<u8>, JsError> {
+        let input = Self::parse(input_json)?;
+        if input.config.animation.is_some() {
+            let frames = Frames::new(&input, &self.font, &self.glyphs);
+            let mut bytes = Vec::new();
+            encode_animation(&frames, OutputFormat::Png, &input, &mut bytes).map_err(js_error)?;
+            return Ok(bytes);
+        }
+        encode_to_vec(&self.render(&input), OutputFormat::Png, &input).map_err(js_error)
+    }
+
+    /// Render `input_json` as an HTML fragment with inline styles.
+    #[wasm_bindgen(js_name = renderHtml)]
+    pub fn render_html(&self, input_json: &str) -> Result<String, JsError> {
+        Ok(render_html(&Self::parse(input_json)?))
+    }
+
+    /// Render `input_json` and return the raw RGBA pixels of the still image.
+    #[wasm_bindgen(js_name = renderRgba)]
+    pub fn render_rgba(&self, input_json: &str) -> Result<RgbaBuffer, JsError> {
+        let image = self.render(&Self::parse(input_json)?);
+        Ok(RgbaBuffer {
+            width: image.width(),
+            height: image.height(),
+            data: image.into_raw(),
+        })
+    }
+}
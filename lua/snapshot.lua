@@ -4,6 +4,8 @@ local highlights = require("snapshot.highlights")
 
 local is_windows = vim.fn.has("win32") == 1 or vim.fn.has("win64") == 1
 local generator_bin = is_windows and "snapshot-generator.exe" or "snapshot-generator"
+local native_lib = is_windows and "snapshot_generator.dll"
+  or (jit.os == "OSX" and "libsnapshot_generator.dylib" or "libsnapshot_generator.so")
 
 --- Resolve the Normal highlight group to get the editor's background and foreground colors.
 --- Falls back to sensible defaults if the highlight group is not set.
@@ -18,6 +20,9 @@ end
 ---@class Config
 ---@field snapshot_dir string? Directory to save snapshots (defaults to $HOME)
 ---@field output_path string? Path to save the snapshot (defaults to snapshot_dir/snapshot-{timestamp}.png)
+---@field filename_template string? Name of generated files with {date}, {time}, {file}, {lang}, {start_line}, {end_line}, {hash} and {ext} placeholders (default: "snapshot-{date}_{time}.{ext}")
+---@field output_encoding string? How generator output sent to stdout (`output_path = "-"`) is written: "raw", "base64" or "data_uri" (default: "raw")
+---@field overwrite string? What to do when the output file exists: "always" replace it, "never" fail, or "rename" to a numbered name (default: "always")
 ---@field scale number? Resolution scale factor for HiDPI/Retina rendering (default: 2)
 ---@field padding number? Padding around the code (default: 80)
 ---@field line_height number? Height of each line in pixels (default: 28)
@@ -25,12 +30,25 @@ end
 ---@field background string? Background color in hex format (default: from Normal highlight group)
 ---@field foreground string? Default text color in hex format (default: from Normal highlight group)
 ---@field clipboard boolean? Enable saving snapshot to clipboard
+---@field save boolean? Save snapshots to a file; false keeps them on the clipboard only, saving anyway if copying fails (default: true)
+---@field clipboard_backend string? Clipboard backend: "auto", "system", "wl_copy", "xclip", "xsel" or "osc52" (default: "auto")
 ---@field shadow boolean? Enable shadow effect (default: true)
 ---@field line_numbers boolean? Show line numbers (default: false)
 ---@field start_line number? Starting line number (default: 1)
 ---@field border_radius number? Corner radius in pixels (default: 5)
 ---@field outer_background string? Background color outside the code card in hex format (default: "#ffffff")
 ---@field outer_padding number? Padding between the code card and outer background edge in pixels (default: 40)
+---@field dpi number? Physical resolution stored in the image metadata (default: 72 × scale)
+---@field format string? Output format: "png", "jpeg", "webp", "avif", "gif" or "html" (default: from output_path extension, else "png")
+---@field quality number? Quality from 1 to 100 for JPEG, lossy WebP and AVIF (default: 90)
+---@field webp_lossless boolean? Encode WebP losslessly (default: true)
+---@field avif_speed number? AVIF encoder speed from 1 (smallest files) to 10 (fastest) (default: 6)
+---@field png_compression string? PNG compression effort: "fast", "default" or "best" (default: "default")
+---@field embed_source boolean? Store the code, filetype and full file path in PNG metadata (default: false)
+---@field html_card boolean? Wrap HTML output in the card with padding, rounded corners and shadow (default: true)
+---@field animation table|boolean? Save a typing animation as an animated PNG or GIF: `true` or { reveal = "chars"|"lines", fps = 20, speed = 30, hold = 2, cursor = true, duration = 1 } (default: nil)
+---@field blending string? Color space for antialiasing and shadows: "srgb" or "linear" (default: "srgb")
+---@field strict boolean? Treat invalid config values and unknown keys as errors instead of warnings (default: false)
 local config = {
   scale = 2,
   padding = 25,
@@ -54,6 +72,111 @@ M.config = config
 ---@param args Config?
 local VERSION = "v0.1.3"
 
+-- Version of the JSON contract with the generator, see `snapshot-generator --capabilities`
+local PROTOCOL_VERSION = 1
+
+-- Config keys that older generators silently ignore, mapped to the capability they require
+local feature_keys = {
+  strict = "validation",
+  blending = "linear_blending",
+  format = "encoder_options",
+  quality = "encoder_options",
+  webp_lossless = "encoder_options",
+  avif_speed = "encoder_options",
+  png_compression = "png_optimization",
+  embed_source = "source_metadata",
+  dpi = "dpi_metadata",
+  animation = "animation",
+  html_card = "html",
+  output_encoding = "stdout_output",
+  filename_template = "filename_template",
+  overwrite = "overwrite",
+  clipboard_backend = "clipboard_backends",
+  save = "clipboard_only",
+}
+
+local capabilities_cache = {}
+
+--- Ask the generator which protocol versions and features it supports.
+--- Returns nil for binaries that predate the capability handshake.
+local function get_capabilities(generator_path)
+  if capabilities_cache[generator_path] == nil then
+    local output = vim.fn.system({ generator_path, "--capabilities" }, "")
+    local ok, decoded = pcall(vim.json.decode, output)
+    if vim.v.shell_error == 0 and ok and type(decoded) == "table" then
+      capabilities_cache[generator_path] = decoded
+    else
+      capabilities_cache[generator_path] = false
+    end
+  end
+  return capabilities_cache[generator_path] or nil
+end
+
+local native_renderer
+
+--- Load the in-process renderer built with `cargo rustc --release --lib --features lua --crate-type cdylib`, if present.
+--- Returns nil when only the standalone generator binary is installed, or when the module was built for another
+--- protocol version, in which case the generator binary is used instead.
+local function get_native_renderer()
+  if native_renderer == nil then
+    native_renderer = false
+    for _, path in ipairs(vim.api.nvim_list_runtime_paths()) do
+      if path:match("snapshot%.nvim") or path:match("snapshot$") then
+        local release_dir = path .. "/generator/target/release/"
+        local loader = package.loadlib(release_dir .. native_lib, "luaopen_snapshot_generator")
+        if loader then
+          local ok, renderer = pcall(loader)
+          local capabilities = ok and type(renderer) == "table" and renderer.capabilities
+          local compatible = type(capabilities) == "table"
+            and vim.tbl_contains(capabilities.protocol_versions or {}, PROTOCOL_VERSION)
+          if compatible and pcall(renderer.load_font, release_dir .. "JetBrainsMono-Regular.ttf") then
+            native_renderer = renderer
+          end
+        end
+        break
+      end
+    end
+  end
+  return native_renderer or nil
+end
+
+--- Drop fields the generator would ignore and tag the payload with the protocol version it understands.
+local function adapt_payload(payload, capabilities)
+  local features = capabilities and capabilities.features or {}
+  local dropped = {}
+  for key, feature in pairs(feature_keys) do
+    if payload.config[key] ~= nil and not vim.tbl_contains(features, feature) then
+      payload.config[key] = nil
+      table.insert(dropped, key)
+    end
+  end
+  if payload.source ~= nil and not vim.tbl_contains(features, "source_metadata") then
+    payload.source = nil
+  end
+  if payload.before ~= nil and not vim.tbl_contains(features, "transition") then
+    payload.before = nil
+    table.insert(dropped, "before")
+  end
+
+  if capabilities and vim.tbl_contains(capabilities.protocol_versions or {}, PROTOCOL_VERSION) then
+    payload.protocol_version = PROTOCOL_VERSION
+  else
+    vim.notify(
+      "snapshot.nvim: the snapshot generator is outdated, run `:lua require('snapshot').install()` to update it",
+      vim.log.levels.WARN
+    )
+  end
+
+  if #dropped > 0 then
+    table.sort(dropped)
+    vim.notify(
+      "snapshot.nvim: ignoring options unsupported by the installed generator: " .. table.concat(dropped, ", "),
+      vim.log.levels.WARN
+    )
+  end
+  return payload
+end
+
 --- Read the installed version from the .version file in the release directory.
 --- Returns nil if the file doesn't exist or can't be read.
 local function read_installed_version(plugin_root)
@@ -216,10 +339,12 @@ M.capture_highlights = function(bufnr)
 end
 
 -- Generate a snapshot image from the current buffer or visual selection
----@param opts table? Optional configuration overrides
+---@param opts table? Optional configuration overrides, plus `before`: a buffer holding an earlier version to animate from
 M.snapshot = function(opts)
-  opts = opts or {}
+  opts = vim.deepcopy(opts or {})
   local bufnr = vim.api.nvim_get_current_buf()
+  local before_bufnr = opts.before
+  opts.before = nil
 
   -- Check if we have a visual selection by checking the marks
   local start_pos = vim.fn.getpos("'<")
@@ -248,6 +373,11 @@ M.snapshot = function(opts)
 
   -- Build the JSON payload
   local buffer_json = require("snapshot.json").build_snapshot_json(bufnr, lines, start_line_num - 1)
+  local before_json
+  if before_bufnr then
+    local before_lines = vim.api.nvim_buf_get_lines(before_bufnr, 0, -1, false)
+    before_json = require("snapshot.json").build_snapshot_json(before_bufnr, before_lines, 0)
+  end
 
   -- Merge config with opts
   local final_config = vim.tbl_deep_extend("force", M.config, opts)
@@ -286,6 +416,13 @@ M.snapshot = function(opts)
 
   -- Normalize user-provided output path (do NOT set a default filename here)
   -- If output_path is nil, we'll remove it from the config so Rust uses its default (timestamped)
+  if final_config.output_path == "-" then
+    vim.notify(
+      'output_path = "-" streams to stdout and is meant for scripts; call render_png on the native module from Lua',
+      vim.log.levels.ERROR
+    )
+    return nil
+  end
   if final_config.output_path then
     -- Expand tilde and env vars
     final_config.output_path = vim.fn.expand(final_config.output_path)
@@ -303,12 +440,15 @@ M.snapshot = function(opts)
       not final_config.output_path:match("%.[pP][nN][gG]$")
       and not final_config.output_path:match("%.[jJ][pP][eE]?[gG]$")
       and not final_config.output_path:match("%.[wW][eE][bB][pP]$")
+      and not final_config.output_path:match("%.[aA][vV][iI][fF]$")
+      and not final_config.output_path:match("%.[gG][iI][fF]$")
+      and not final_config.output_path:match("%.[hH][tT][mM][lL]?$")
       and vim.fn.isdirectory(final_config.output_path) == 0
     then
       vim.notify(
         "Warning: output_path '"
           .. final_config.output_path
-          .. "' may not have a valid image extension (.png, .jpg, .jpeg, .webp).",
+          .. "' may not have a valid extension (.png, .jpg, .jpeg, .webp, .avif, .gif, .html).",
         vim.log.levels.WARN
       )
     end
@@ -321,13 +461,47 @@ M.snapshot = function(opts)
       config_for_json[k] = v
     end
   end
+  -- `animation = true` (or an empty table) means the default animation, which must encode as a JSON object
+  local animation = config_for_json.animation
+  if animation == false then
+    config_for_json.animation = nil
+  elseif animation == true or (type(animation) == "table" and vim.tbl_isempty(animation)) then
+    config_for_json.animation = vim.empty_dict()
+  end
+  -- A transition is an animation, so `before` alone turns it on
+  if before_json and config_for_json.animation == nil then
+    config_for_json.animation = vim.empty_dict()
+  end
 
+  local buffer_path = vim.api.nvim_buf_get_name(bufnr)
   local payload = {
     lines = buffer_json,
     config = config_for_json,
+    source = {
+      language = vim.bo[bufnr].filetype ~= "" and vim.bo[bufnr].filetype or nil,
+      path = buffer_path ~= "" and vim.fn.fnamemodify(buffer_path, ":p") or nil,
+    },
+    before = before_json,
   }
 
-  local json_string = vim.fn.json_encode(payload)
+  -- Render in-process when the native module is available, skipping process startup and JSON encoding
+  local native = get_native_renderer()
+  if native then
+    local ok, saved_path, warnings = pcall(native.render, payload.lines, payload.config, payload.source, payload.before)
+    if not ok then
+      vim.notify("Failed to generate snapshot: " .. tostring(saved_path), vim.log.levels.ERROR)
+      return nil
+    end
+    for _, warning in ipairs(warnings) do
+      vim.notify("snapshot.nvim: Warning: " .. warning, vim.log.levels.WARN)
+    end
+    if saved_path == nil then
+      vim.notify("Snapshot copied to clipboard", vim.log.levels.INFO)
+      return nil
+    end
+    vim.notify("Snapshot saved to: " .. saved_path, vim.log.levels.INFO)
+    return saved_path
+  end
 
   -- Find the generator binary using multiple methods for reliability
   local generator_path
@@ -380,6 +554,8 @@ M.snapshot = function(opts)
     return nil
   end
 
+  local json_string = vim.fn.json_encode(adapt_payload(payload, get_capabilities(generator_path)))
+
   -- Run the generator (pass JSON via stdin using vim.fn.system's second argument)
   local output = vim.fn.system({ generator_path }, json_string)
 
@@ -388,7 +564,20 @@ M.snapshot = function(opts)
     return nil
   end
 
-  local output_path = output:gsub("%s+", "")
+  -- The generator reports recoverable problems as "Warning: ..." lines before the saved path,
+  -- which is replaced by "Copied to clipboard" when `save = false` skipped the file
+  local output_path
+  for _, line in ipairs(vim.split(output, "\n", { trimempty = true })) do
+    if line:match("^Warning: ") then
+      vim.notify("snapshot.nvim: " .. line, vim.log.levels.WARN)
+    elseif line:match("^Copied to clipboard%s*$") then
+      vim.notify("Snapshot copied to clipboard", vim.log.levels.INFO)
+      return nil
+    else
+      output_path = line:gsub("%s+$", "")
+    end
+  end
+
   vim.notify("Snapshot saved to: " .. output_path, vim.log.levels.INFO)
   return output_path
 end